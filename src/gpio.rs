@@ -45,9 +45,10 @@ pub trait ExtiPin: InputPin {
 }
 
 macro_rules! gpio_as_var {
-	($gpiox:ident, $GPIOx:ident, $Portx:ident, $iopxen:ident, $extiport:expr, $($pxi:ident: ($PXi:ident, $pin:expr, $exticrx:ident, $crx:ident),)+) => {
+	($gpiox:ident, $GPIOx:ident, $Portx:ident, $extiport:expr, $($pxi:ident: ($PXi:ident, $pin:expr, $exticrx:ident, $crx:ident),)+) => {
 		pub mod $gpiox {
-			use super::pac::{$GPIOx, RCC, EXTI, AFIO};
+			use super::pac::{$GPIOx, EXTI, AFIO};
+			use crate::rcc::Enable;
 			use super::{
 				GpioSpeed,
 				InputPin,
@@ -75,7 +76,7 @@ macro_rules! gpio_as_var {
 
 			impl $Portx {
 				pub fn enable() -> $Portx {
-					unsafe { (*RCC::ptr()).apb2enr.modify(|_, w|w.$iopxen().set_bit()); }
+					$GPIOx::enable();
 					$Portx { 
 						$(
 							$pxi: $PXi { _mode: Reset },
@@ -277,17 +278,18 @@ macro_rules! gpio_as_var {
 }
 
 macro_rules! gpio_as_fn {
-	($PORTx:ident, $GPIOx:ident, $iopxen:ident, $extiport:expr, $(($PXi:ident, $pin:expr, $exticrx:ident, $crx:ident),)+) => {
+	($PORTx:ident, $GPIOx:ident, $extiport:expr, $(($PXi:ident, $pin:expr, $exticrx:ident, $crx:ident),)+) => {
 		#[allow(non_snake_case)]
 		pub mod $PORTx{
-			use super::pac::{$GPIOx, EXTI, AFIO, RCC};
+			use super::pac::{$GPIOx, EXTI, AFIO};
+			use crate::rcc::Enable;
 			use super::{
 				GpioSpeed,
 				Edge
 			};
 			
 			pub fn enable() {
-				unsafe { (*RCC::ptr()).apb2enr.modify(|_, w|w.$iopxen().set_bit()); }
+				$GPIOx::enable();
 			}
 
 			$(
@@ -451,7 +453,7 @@ macro_rules! gpio_as_fn {
 
 
 gpio_as_fn!{
-	PORTA, GPIOA, iopaen, 0,
+	PORTA, GPIOA, 0,
 	(A0, 0, exticr1, crl),
 	(A1, 1, exticr1, crl),
 	(A2, 2, exticr1, crl),
@@ -471,7 +473,7 @@ gpio_as_fn!{
 }
 
 gpio_as_fn!{
-	PORTB, GPIOB, iopben, 1,
+	PORTB, GPIOB, 1,
 	(B0, 0, exticr1, crl),
 	(B1, 1, exticr1, crl),
 	(B2, 2, exticr1, crl),
@@ -491,7 +493,7 @@ gpio_as_fn!{
 }
 
 gpio_as_fn!{
-	PORTC, GPIOC, iopcen, 3,
+	PORTC, GPIOC, 3,
 	(C0, 0, exticr1, crl),
 	(C1, 1, exticr1, crl),
 	(C2, 2, exticr1, crl),
@@ -511,7 +513,7 @@ gpio_as_fn!{
 }
 
 gpio_as_var!{
-	gpioa, GPIOA, Porta, iopaen, 0,
+	gpioa, GPIOA, Porta, 0,
 	pa0: (PA0, 0, exticr1, crl),
 	pa1: (PA1, 1, exticr1, crl),
 	pa2: (PA2, 2, exticr1, crl),
@@ -531,7 +533,7 @@ gpio_as_var!{
 }
 
 gpio_as_var!{
-	gpiob, GPIOB, Portb, iopben, 1,
+	gpiob, GPIOB, Portb, 1,
 	pb0: (PB0, 0, exticr1, crl),
 	pb1: (PB1, 1, exticr1, crl),
 	pb2: (PB2, 2, exticr1, crl),
@@ -551,7 +553,7 @@ gpio_as_var!{
 }
 
 gpio_as_var!{
-	gpioc, GPIOC, Portc, iopcen, 3,
+	gpioc, GPIOC, Portc, 3,
 	pc0: (PC0, 0, exticr1, crl),
 	pc1: (PC1, 1, exticr1, crl),
 	pc2: (PC2, 2, exticr1, crl),
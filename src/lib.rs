@@ -18,4 +18,5 @@ pub use stm32f1::stm32f107 as pac;
 pub mod systime;
 pub mod prelude;
 pub mod gpio;
-pub mod rcc;
\ No newline at end of file
+pub mod rcc;
+pub mod profile;
\ No newline at end of file
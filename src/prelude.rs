@@ -1,2 +1,2 @@
-pub use crate::systime::{SysTime, U32time, MicroSeconds, MilliSeconds};
+pub use crate::systime::{SysTime, U32time, NanoSeconds, MicroSeconds, MilliSeconds, InstantMs, InstantUs, DurationMs, DurationUs};
 pub use crate::rcc::RccExt;
@@ -0,0 +1,144 @@
+use crate::systime::SysTime;
+
+/// Converts core clock cycles to nanoseconds at the current HCLK.
+pub fn cycles_to_ns(cycles: u32) -> u64 {
+    cycles as u64 * 1_000_000_000 / SysTime::ahb_hz() as u64
+}
+
+/// Stopwatch on the DWT cycle counter. Unlike `SysTime::dwt_start` it only
+/// snapshots CYCCNT, so any number of measurements can overlap. Elapsed
+/// cycles are wrap safe up to 2^32 cycles (~59 s at 72 MHz).
+#[derive(Debug, Clone, Copy)]
+pub struct CycleMeasure {
+    start: u32,
+}
+impl CycleMeasure {
+    pub fn start() -> Self {
+        SysTime::dwt_enable();
+        CycleMeasure { start: SysTime::dwt_now() }
+    }
+
+    pub fn elapsed(&self) -> u32 {
+        SysTime::dwt_now().wrapping_sub(self.start)
+    }
+
+    pub fn elapsed_ns(&self) -> u64 {
+        cycles_to_ns(self.elapsed())
+    }
+
+    /// Returns the cycles since the last start and starts over.
+    pub fn lap(&mut self) -> u32 {
+        let now = SysTime::dwt_now();
+        let cycles = now.wrapping_sub(self.start);
+        self.start = now;
+        cycles
+    }
+}
+
+/// Cycle statistics of a named code section: count, min, max, average and a
+/// histogram where bucket `n` counts runs of `2^n..2^(n+1)` cycles.
+///
+/// ```ignore
+/// let mut stats = CycleStats::new("filter");
+/// for sample in samples {
+///     let _scope = stats.scope();
+///     filter.push(sample);
+/// }
+/// stats.print();
+/// ```
+#[derive(Debug, Clone)]
+pub struct CycleStats {
+    name: &'static str,
+    count: u32,
+    min: u32,
+    max: u32,
+    total: u64,
+    histogram: [u32; 32],
+}
+impl CycleStats {
+    pub const fn new(name: &'static str) -> Self {
+        CycleStats {
+            name,
+            count: 0,
+            min: u32::MAX,
+            max: 0,
+            total: 0,
+            histogram: [0; 32],
+        }
+    }
+
+    pub fn record(&mut self, cycles: u32) {
+        self.count = self.count.saturating_add(1);
+        self.min = self.min.min(cycles);
+        self.max = self.max.max(cycles);
+        self.total = self.total.saturating_add(cycles as u64);
+        let bucket = &mut self.histogram[cycles.checked_ilog2().unwrap_or(0) as usize];
+        *bucket = bucket.saturating_add(1);
+    }
+
+    /// Runs `f` and records its cycles.
+    pub fn measure<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        let measure = CycleMeasure::start();
+        let result = f();
+        self.record(measure.elapsed());
+        result
+    }
+
+    /// Records the cycles until the returned guard is dropped.
+    pub fn scope(&mut self) -> Scope<'_> {
+        Scope { stats: self, measure: CycleMeasure::start() }
+    }
+
+    pub fn reset(&mut self) {
+        *self = CycleStats::new(self.name);
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Shortest run in cycles, 0 if nothing was recorded.
+    pub fn min(&self) -> u32 {
+        if self.count == 0 { 0 } else { self.min }
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    pub fn avg(&self) -> u32 {
+        if self.count == 0 { 0 } else { (self.total / self.count as u64) as u32 }
+    }
+
+    pub fn histogram(&self) -> &[u32; 32] {
+        &self.histogram
+    }
+
+    /// Prints the statistics over RTT, needs `rtt_init_print!` in the application.
+    pub fn print(&self) {
+        rtt_target::rprintln!("{}: {} runs, cycles min {} avg {} max {}, ns min {} avg {} max {}",
+            self.name, self.count,
+            self.min(), self.avg(), self.max(),
+            cycles_to_ns(self.min()), cycles_to_ns(self.avg()), cycles_to_ns(self.max()));
+        for (bucket, &runs) in self.histogram.iter().enumerate() {
+            if runs != 0 {
+                rtt_target::rprintln!("  >= {:>10} cycles: {}", 1u32 << bucket, runs);
+            }
+        }
+    }
+}
+
+/// Guard from `CycleStats::scope`, records into its section when dropped.
+pub struct Scope<'a> {
+    stats: &'a mut CycleStats,
+    measure: CycleMeasure,
+}
+impl Drop for Scope<'_> {
+    fn drop(&mut self) {
+        self.stats.record(self.measure.elapsed());
+    }
+}
@@ -1,6 +1,124 @@
-use crate::pac::{FLASH, RCC};
+use crate::pac::{FLASH, PWR, RCC};
+use crate::systime::{SysTime, MilliSeconds};
+use crate::gpio::{PORTA, GpioSpeed};
+#[cfg(feature = "css-nmi")]
+use cortex_m_rt::exception;
+
+mod enable;
+pub use enable::{Bus, BusClock, Enable, Reset, LowPowerEnable, Sram};
+mod calibrate;
+pub use calibrate::{calibrate_hsi_lse, calibrate_hsi_external, HsiCalibration};
 
 const HSI: u32 = 8_000_000;
+const LSE: u32 = 32_768;
+const LSI: u32 = 40_000;
+
+use limits::*;
+
+/// Datasheet limits of the value line (STM32F100), 24 MHz with PREDIV1 on HSE.
+#[cfg(feature = "stm32f100")]
+mod limits {
+    pub const SYSCLK_MAX: u32 = 24_000_000;
+    pub const PCLK1_MAX: u32 = 24_000_000;
+    pub const PCLK2_MAX: u32 = 24_000_000;
+    pub const ADCCLK_MAX: u32 = 12_000_000;
+    pub const HSE_MIN: u32 = 4_000_000;
+    pub const HSE_MAX: u32 = 24_000_000;
+    pub const HSE_BYP_MAX: u32 = 24_000_000;
+    pub const PLL_MUL_MIN: u8 = 2;
+    pub const PLL_MUL_MAX: u8 = 16;
+    pub const PLL_IN_MIN: u32 = 1_000_000;
+    pub const PLL_IN_MAX: u32 = 24_000_000;
+    pub const PREDIV_MAX: u8 = 16;
+}
+
+/// Datasheet limits of the access line (STM32F101), 36 MHz.
+#[cfg(feature = "stm32f101")]
+mod limits {
+    pub const SYSCLK_MAX: u32 = 36_000_000;
+    pub const PCLK1_MAX: u32 = 36_000_000;
+    pub const PCLK2_MAX: u32 = 36_000_000;
+    pub const ADCCLK_MAX: u32 = 14_000_000;
+    pub const HSE_MIN: u32 = 4_000_000;
+    pub const HSE_MAX: u32 = 16_000_000;
+    pub const HSE_BYP_MAX: u32 = 25_000_000;
+    pub const PLL_MUL_MIN: u8 = 2;
+    pub const PLL_MUL_MAX: u8 = 16;
+    pub const PLL_IN_MIN: u32 = 1_000_000;
+    pub const PLL_IN_MAX: u32 = 25_000_000;
+    pub const PREDIV_MAX: u8 = 2;
+}
+
+/// Datasheet limits of the performance line (STM32F103), 72 MHz.
+#[cfg(feature = "stm32f103")]
+mod limits {
+    pub const SYSCLK_MAX: u32 = 72_000_000;
+    pub const PCLK1_MAX: u32 = 36_000_000;
+    pub const PCLK2_MAX: u32 = 72_000_000;
+    pub const ADCCLK_MAX: u32 = 14_000_000;
+    pub const HSE_MIN: u32 = 4_000_000;
+    pub const HSE_MAX: u32 = 16_000_000;
+    pub const HSE_BYP_MAX: u32 = 25_000_000;
+    pub const PLL_MUL_MIN: u8 = 2;
+    pub const PLL_MUL_MAX: u8 = 16;
+    pub const PLL_IN_MIN: u32 = 1_000_000;
+    pub const PLL_IN_MAX: u32 = 25_000_000;
+    pub const PREDIV_MAX: u8 = 2;
+}
+
+/// Datasheet limits of the connectivity line (STM32F105/107), 72 MHz with
+/// PREDIV1 fed from HSE or PLL2.
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+mod limits {
+    pub const SYSCLK_MAX: u32 = 72_000_000;
+    pub const PCLK1_MAX: u32 = 36_000_000;
+    pub const PCLK2_MAX: u32 = 72_000_000;
+    pub const ADCCLK_MAX: u32 = 14_000_000;
+    pub const HSE_MIN: u32 = 3_000_000;
+    pub const HSE_MAX: u32 = 25_000_000;
+    pub const HSE_BYP_MAX: u32 = 50_000_000;
+    pub const PLL_MUL_MIN: u8 = 4;
+    pub const PLL_MUL_MAX: u8 = 9;
+    pub const PLL_IN_MIN: u32 = 3_000_000;
+    pub const PLL_IN_MAX: u32 = 12_000_000;
+    pub const PREDIV_MAX: u8 = 16;
+}
+
+const HSE_BYP_MIN: u32 = 1_000_000;
+
+// PLL2/PLL3 input after PREDIV2 and their output range
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+const PLL23_IN_MIN: u32 = 3_000_000;
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+const PLL23_IN_MAX: u32 = 5_000_000;
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+const PLL23_OUT_MIN: u32 = 40_000_000;
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+const PLL23_OUT_MAX: u32 = 74_000_000;
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+const PLL23_MULS: [u8; 9] = [8, 9, 10, 11, 12, 13, 14, 16, 20];
+
+const USBCLK: u32 = 48_000_000;
+// Flash half-cycle access is only allowed up to this SYSCLK, without PLL
+const HALF_CYCLE_MAX: u32 = 8_000_000;
+
+const HSE_STARTUP_TIMEOUT: u32 = 100;
+const PLL_LOCK_TIMEOUT: u32 = 2;
+const LSE_STARTUP_TIMEOUT: u32 = 5000;
+const LSI_STARTUP_TIMEOUT: u32 = 2;
+
+#[cfg(not(any(feature = "stm32f105", feature = "stm32f107")))]
+const MCO_MASK: u32 = 0b111;
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+const MCO_MASK: u32 = 0b1111;
+
+const AHB_DIVS: [u16; 9] = [1, 2, 4, 8, 16, 64, 128, 256, 512];
+const APB_DIVS: [u16; 5] = [1, 2, 4, 8, 16];
+const ADC_DIVS: [u16; 4] = [2, 4, 6, 8];
+
+static mut G_HSE: u32 = 0;
+static mut G_CSS_CONFIG: Option<RccConfig> = None;
+static mut G_CSS_CALLBACK: Option<fn(&Clock)> = None;
 
 pub trait RccExt {
     fn rcc_config() -> RccConfig;
@@ -10,188 +128,1531 @@ pub trait RccExt {
 
 impl RccExt for crate::pac::RCC {
     fn rcc_config() -> RccConfig {
-        RccConfig {
-            hse: 0,
-            pll: 0,
-            apb1_pre: 2,
-            apb2_pre: 1,
-            adc_pre: 6,
-            hse_byp: false
-        }
+        RccConfig::new()
     }
-    
+
     fn rtc_config() -> RtcConfig {
-        RtcConfig
+        RtcConfig {
+            source: RtcClock::Lse,
+            lse_byp: false,
+            hse: 0,
+            timeout: LSE_STARTUP_TIMEOUT,
+            backup_reset: false,
+        }
     }
-    
+
     fn mco_config() -> McoConfig {
-        McoConfig
+        McoConfig {
+            source: Mco::Sysclk,
+        }
+    }
+
+}
+
+/// Reason why a clock configuration could not be produced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockError {
+    /// HSE frequency is outside the oscillator (or bypass) input range.
+    HseOutOfRange,
+    /// SYSCLK target is zero or above the chip limit.
+    SysclkOutOfRange,
+    /// No APB1 prescaler keeps PCLK1 within the chip limit.
+    Pclk1OutOfRange,
+    /// No APB2 prescaler keeps PCLK2 within the chip limit.
+    Pclk2OutOfRange,
+    /// No ADC prescaler keeps ADCCLK within the chip limit.
+    AdcclkOutOfRange,
+    /// AHB, APB or ADC divider is not one the hardware supports.
+    InvalidPrescaler,
+    /// PLL multiplier is outside the supported range.
+    InvalidPllMul,
+    /// USB was required but the PLL does not run from HSE or its output is
+    /// neither 48 MHz nor 72 MHz.
+    UsbClockUnavailable,
+    /// PLL input after the HSE predivider is outside the allowed range.
+    PllInputOutOfRange,
+    /// PLL2 input or output is outside the allowed range (connectivity line).
+    Pll2OutOfRange,
+    /// PLL3 input or output is outside the allowed range (connectivity line).
+    Pll3OutOfRange,
+    /// HSE did not report ready within the startup timeout.
+    HseTimeout,
+    /// PLL, PLL2 or PLL3 did not lock within the lock timeout.
+    PllLockTimeout,
+    /// LSE did not report ready within the startup timeout.
+    LseTimeout,
+    /// LSI did not report ready within the startup timeout.
+    LsiTimeout,
+    /// RTC already runs from another source and the backup domain may not be reset.
+    RtcSourceLocked,
+    /// HSI calibration needs SYSCLK to come from HSI.
+    HsiNotInUse,
+    /// HSI calibration against LSE needs LSE running as RTC clock.
+    LseNotRtcClock,
+    /// The calibration reference stopped toggling.
+    ReferenceTimeout,
+    /// The calibration reference frequency is outside the supported range.
+    ReferenceOutOfRange,
+    /// Half-cycle flash access or disabled prefetch is not allowed at this clock.
+    FlashAccessInvalid,
+    /// The running SysTick rate cannot be kept at the new HCLK.
+    SysTickOutOfRange,
+}
+impl ClockError {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ClockError::HseOutOfRange => "HSE frequency out of range",
+            ClockError::SysclkOutOfRange => "SYSCLK out of range",
+            ClockError::Pclk1OutOfRange => "PCLK1 out of range",
+            ClockError::Pclk2OutOfRange => "PCLK2 out of range",
+            ClockError::AdcclkOutOfRange => "ADCCLK out of range",
+            ClockError::InvalidPrescaler => "invalid prescaler",
+            ClockError::InvalidPllMul => "invalid PLL multiplier",
+            ClockError::UsbClockUnavailable => "PLL output cannot provide 48 MHz USB clock",
+            ClockError::PllInputOutOfRange => "PLL input out of range",
+            ClockError::Pll2OutOfRange => "PLL2 out of range",
+            ClockError::Pll3OutOfRange => "PLL3 out of range",
+            ClockError::HseTimeout => "HSE startup timeout",
+            ClockError::PllLockTimeout => "PLL lock timeout",
+            ClockError::LseTimeout => "LSE startup timeout",
+            ClockError::LsiTimeout => "LSI startup timeout",
+            ClockError::RtcSourceLocked => "RTC clock source locked",
+            ClockError::HsiNotInUse => "SYSCLK is not derived from HSI",
+            ClockError::LseNotRtcClock => "LSE is not the running RTC clock",
+            ClockError::ReferenceTimeout => "calibration reference timeout",
+            ClockError::ReferenceOutOfRange => "calibration reference frequency out of range",
+            ClockError::FlashAccessInvalid => "flash access mode not allowed at this clock",
+            ClockError::SysTickOutOfRange => "SysTick rate out of range for HCLK",
+        }
     }
-    
 }
-pub struct McoConfig;
-pub struct RtcConfig;
+
+/// Clock routed to the MCO pin (PA8).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mco {
+    Sysclk = 0b0100,
+    Hsi = 0b0101,
+    Hse = 0b0110,
+    PllDiv2 = 0b0111,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    Pll2 = 0b1000,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    Pll3Div2 = 0b1001,
+    /// External 3-25 MHz oscillator, for the Ethernet PHY
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    Xt1 = 0b1010,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    Pll3 = 0b1011,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct McoConfig {
+    source: Mco,
+}
+impl McoConfig {
+    pub fn source(mut self, source: Mco) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Puts PA8 into alternate push-pull at 50 MHz and routes the clock to it.
+    /// The pin is limited to 50 MHz, so SYSCLK above that will be distorted.
+    pub fn enable(self) {
+        PORTA::enable();
+        PORTA::A8::alternate_push_pull(GpioSpeed::Mhz50);
+
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|r, w| unsafe { w.bits((r.bits() & !(MCO_MASK << 24)) | ((self.source as u32) << 24)) });
+    }
+
+    /// Stops the clock output, PA8 is left in alternate mode.
+    pub fn disable(self) {
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|r, w| unsafe { w.bits(r.bits() & !(MCO_MASK << 24)) });
+    }
+}
+
+/// RTC kernel clock source (RTCSEL).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcClock {
+    Lse = 0b01,
+    Lsi = 0b10,
+    HseDiv128 = 0b11,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RtcConfig {
+    source: RtcClock,
+    lse_byp: bool,
+    hse: u32,
+    timeout: u32,
+    backup_reset: bool,
+}
+impl RtcConfig {
+    /// Runs the RTC from a 32.768 kHz crystal on OSC32 (default).
+    pub fn lse_use(mut self) -> Self {
+        self.source = RtcClock::Lse;
+        self.lse_byp = false;
+        self
+    }
+
+    /// Runs the RTC from an external 32.768 kHz clock on OSC32_IN.
+    pub fn lse_byp_use(mut self) -> Self {
+        self.source = RtcClock::Lse;
+        self.lse_byp = true;
+        self
+    }
+
+    /// Runs the RTC from the ~40 kHz internal RC oscillator.
+    pub fn lsi_use(mut self) -> Self {
+        self.source = RtcClock::Lsi;
+        self
+    }
+
+    /// Runs the RTC from HSE / 128. HSE is started if it is not running yet.
+    /// `hse_clock` is the crystal frequency, `tune` rejects 0.
+    pub fn hse_div128_use(mut self, hse_clock: u32) -> Self {
+        self.source = RtcClock::HseDiv128;
+        self.hse = hse_clock;
+        self
+    }
+
+    /// How long to wait for the oscillator to become ready, 5 s by default.
+    /// This is meant for the slow starting LSE; LSI is capped at 2 ms and
+    /// HSE at 100 ms, their worst case startup times.
+    pub fn timeout(mut self, timeout: MilliSeconds) -> Self {
+        self.timeout = timeout.0;
+        self
+    }
+
+    /// Allows a backup domain reset when the RTC already runs from another
+    /// source. This clears the backup registers.
+    pub fn backup_reset(mut self) -> Self {
+        self.backup_reset = true;
+        self
+    }
+
+    /// Starts the selected oscillator and enables the RTC clock.
+    /// Returns the RTC kernel frequency.
+    pub fn tune(self) -> Result<u32, ClockError> {
+        if self.source == RtcClock::HseDiv128 && self.hse == 0 {
+            return Err(ClockError::HseOutOfRange);
+        }
+
+        let rcc = unsafe { &*RCC::ptr() };
+        let pwr = unsafe { &*PWR::ptr() };
+
+        PWR::enable();
+        crate::pac::BKP::enable();
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+        let rtcsel = (rcc.bdcr.read().bits() >> 8) & 0b11;
+        if rtcsel != 0 && rtcsel != self.source as u32 {
+            if !self.backup_reset {
+                return Err(ClockError::RtcSourceLocked);
+            }
+            rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+        }
+
+        let freq = match self.source {
+            RtcClock::Lse => {
+                rcc.bdcr.modify(|_, w| w.lsebyp().bit(self.lse_byp).lseon().set_bit());
+                if !wait_ready(self.timeout, || rcc.bdcr.read().lserdy().bit_is_set()) {
+                    rcc.bdcr.modify(|_, w| w.lseon().clear_bit().lsebyp().clear_bit());
+                    return Err(ClockError::LseTimeout);
+                }
+                LSE
+            }
+            RtcClock::Lsi => {
+                rcc.csr.modify(|_, w| w.lsion().set_bit());
+                if !wait_ready(self.timeout.min(LSI_STARTUP_TIMEOUT), || rcc.csr.read().lsirdy().bit_is_set()) {
+                    rcc.csr.modify(|_, w| w.lsion().clear_bit());
+                    return Err(ClockError::LsiTimeout);
+                }
+                LSI
+            }
+            RtcClock::HseDiv128 => {
+                if rcc.cr.read().hserdy().bit_is_clear() {
+                    rcc.cr.modify(|_, w| w.hseon().set_bit());
+                    if !wait_ready(self.timeout.min(HSE_STARTUP_TIMEOUT), || rcc.cr.read().hserdy().bit_is_set()) {
+                        rcc.cr.modify(|_, w| w.hseon().clear_bit());
+                        return Err(ClockError::HseTimeout);
+                    }
+                }
+                self.hse / 128
+            }
+        };
+
+        rcc.bdcr.modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << 8)) | ((self.source as u32) << 8)) });
+        rcc.bdcr.modify(|_, w| w.rtcen().set_bit());
+
+        Ok(freq)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct RccConfig {
     hse: u32,
     pll: u8,
+    prediv: u8,
+    ahb_pre: u16,
     apb1_pre: u8,
     apb2_pre: u8,
     adc_pre: u8,
     hse_byp: bool,
+    sysclk: u32,
+    hclk: u32,
+    pclk1: u32,
+    pclk2: u32,
+    adcclk: u32,
+    hse_timeout: u32,
+    hsi_fallback: bool,
+    hsi_trim: Option<u8>,
+    css: bool,
+    usb: bool,
+    #[cfg(not(feature = "stm32f100"))]
+    prefetch: bool,
+    half_cycle: bool,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    prediv1_pll2: bool,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    prediv2: u8,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pll2: u8,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pll3: u8,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    i2s2_pll3: bool,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    i2s3_pll3: bool,
+}
+impl Default for RccConfig {
+    fn default() -> Self {
+        RccConfig::new()
+    }
 }
+
 impl RccConfig {
-    pub fn hse_byp_use(mut self, clock: u32) -> Self {
+    /// Reset configuration: HSI, no PLL, APB1 /2, ADC /6. Same as
+    /// `RCC::rcc_config()` but usable in const context.
+    pub const fn new() -> Self {
+        RccConfig {
+            hse: 0,
+            pll: 0,
+            prediv: 1,
+            ahb_pre: 1,
+            apb1_pre: 2,
+            apb2_pre: 1,
+            adc_pre: 6,
+            hse_byp: false,
+            sysclk: 0,
+            hclk: 0,
+            pclk1: 0,
+            pclk2: 0,
+            adcclk: 0,
+            hse_timeout: HSE_STARTUP_TIMEOUT,
+            hsi_fallback: false,
+            hsi_trim: None,
+            css: false,
+            usb: false,
+            #[cfg(not(feature = "stm32f100"))]
+            prefetch: true,
+            half_cycle: false,
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            prediv1_pll2: false,
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            prediv2: 1,
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            pll2: 0,
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            pll3: 0,
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            i2s2_pll3: false,
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            i2s3_pll3: false,
+        }
+    }
+
+    pub const fn hse_byp_use(mut self, clock: u32) -> Self {
         self.hse_byp = true;
         self.hse = clock;
         self
     }
 
-    pub fn hse_use(mut self, hse_clock: u32) -> Self {
+    pub const fn hse_use(mut self, hse_clock: u32) -> Self {
         self.hse = hse_clock;
         self
     }
-    
-    pub fn pll(mut self, pll_mul: u8) -> Self {
+
+    pub const fn pll(mut self, pll_mul: u8) -> Self {
         self.pll = pll_mul;
         self
     }
-    
-    pub fn apb1(mut self, div: u8) -> Self {
+
+    /// AHB prescaler, 1..512. HCLK feeds the core, SysTick and both APB buses.
+    pub const fn ahb(mut self, div: u16) -> Self {
+        self.ahb_pre = div;
+        self
+    }
+
+    pub const fn apb1(mut self, div: u8) -> Self {
         self.apb1_pre = div;
         self
     }
-    
-    pub fn apb2(mut self, div: u8) -> Self {
+
+    pub const fn apb2(mut self, div: u8) -> Self {
         self.apb2_pre = div;
         self
     }
 
-    pub fn adc(mut self, div: u8) -> Self {
+    pub const fn adc(mut self, div: u8) -> Self {
         self.adc_pre = div;
         self
     }
-    
+
+    /// Target SYSCLK frequency. The PLL source, PLLMUL and HSE predivider
+    /// are chosen by `tune()`, overriding `pll()`.
+    pub const fn sysclk(mut self, freq: u32) -> Self {
+        self.sysclk = freq;
+        self
+    }
+
+    /// Target HCLK frequency, overriding `ahb()`.
+    pub const fn hclk(mut self, freq: u32) -> Self {
+        self.hclk = freq;
+        self
+    }
+
+    /// Target PCLK1 frequency, overriding `apb1()`.
+    pub const fn pclk1(mut self, freq: u32) -> Self {
+        self.pclk1 = freq;
+        self
+    }
+
+    /// Target PCLK2 frequency, overriding `apb2()`.
+    pub const fn pclk2(mut self, freq: u32) -> Self {
+        self.pclk2 = freq;
+        self
+    }
+
+    /// Target ADC clock frequency, overriding `adc()`.
+    pub const fn adcclk(mut self, freq: u32) -> Self {
+        self.adcclk = freq;
+        self
+    }
+
+    /// How long to wait for HSE to become ready, 100 ms by default.
+    pub const fn hse_timeout(mut self, timeout: MilliSeconds) -> Self {
+        self.hse_timeout = timeout.0;
+        self
+    }
+
+    /// If HSE fails to start, run the same targets from HSI instead of
+    /// returning `ClockError::HseTimeout`. With `usb()` the fallback fails
+    /// with `ClockError::UsbClockUnavailable`, as USB cannot run from HSI.
+    pub const fn hsi_fallback(mut self) -> Self {
+        self.hsi_fallback = true;
+        self
+    }
+
+    /// Requires a 48 MHz USB clock, restricting the PLL output to 48 MHz
+    /// (USBPRE /1) or 72 MHz (USBPRE /1.5). HSI is not accurate enough for
+    /// USB, so HSE must be configured as PLL source.
+    #[cfg(any(feature = "stm32f103", feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn usb(mut self) -> Self {
+        self.usb = true;
+        self
+    }
+
+    /// PREDIV1 divider, 1..16, between the PREDIV1 source (HSE) and the PLL.
+    #[cfg(any(feature = "stm32f100", feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn prediv1(mut self, div: u8) -> Self {
+        self.prediv = div;
+        self
+    }
+
+    /// Feeds PREDIV1 from PLL2 instead of HSE.
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn prediv1_pll2(mut self) -> Self {
+        self.prediv1_pll2 = true;
+        self
+    }
+
+    /// PREDIV2 divider, 1..16, between HSE and PLL2/PLL3.
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn prediv2(mut self, div: u8) -> Self {
+        self.prediv2 = div;
+        self
+    }
+
+    /// PLL2 multiplier: 8..14, 16 or 20.
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn pll2(mut self, pll2_mul: u8) -> Self {
+        self.pll2 = pll2_mul;
+        self
+    }
+
+    /// PLL3 multiplier: 8..14, 16 or 20.
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn pll3(mut self, pll3_mul: u8) -> Self {
+        self.pll3 = pll3_mul;
+        self
+    }
+
+    /// Clocks I2S2 from the PLL3 VCO (2 x PLL3) instead of SYSCLK.
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn i2s2_pll3(mut self) -> Self {
+        self.i2s2_pll3 = true;
+        self
+    }
+
+    /// Clocks I2S3 from the PLL3 VCO (2 x PLL3) instead of SYSCLK.
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub const fn i2s3_pll3(mut self) -> Self {
+        self.i2s3_pll3 = true;
+        self
+    }
+
+    /// HSITRIM value, 0..31, e.g. from a stored `HsiCalibration::trim`.
+    /// The reset value 16 is kept if not set.
+    pub const fn hsi_trim(mut self, trim: u8) -> Self {
+        self.hsi_trim = Some(trim);
+        self
+    }
+
+    /// Enables the clock security system once HSE is running. On HSE failure
+    /// `css_nmi_handler()` moves the same targets to HSI, reloads SysTime and
+    /// calls the function registered with `on_hse_failure()`. The `css-nmi`
+    /// feature installs it as NMI handler, otherwise call it from your own.
+    pub const fn css(mut self) -> Self {
+        self.css = true;
+        self
+    }
+
+    /// Flash prefetch buffer, on by default. Turning it off saves some power
+    /// at the cost of throughput; it must stay on if HCLK is divided.
+    #[cfg(not(feature = "stm32f100"))]
+    pub const fn prefetch(mut self, on: bool) -> Self {
+        self.prefetch = on;
+        self
+    }
+
+    /// Flash half-cycle access, lowering power at up to 8 MHz SYSCLK from
+    /// HSI or HSE without PLL and with HCLK undivided.
+    pub const fn half_cycle(mut self, on: bool) -> Self {
+        self.half_cycle = on;
+        self
+    }
+
+    /// Returns the clock tree `tune()` would produce, without touching hardware.
+    pub const fn solve(self) -> Result<Clock, ClockError> {
+        match self.resolve() {
+            Ok(cfg) => Ok(cfg.clock()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Panics if the configuration is invalid. In a `const` item this turns
+    /// any violated clock constraint into a build error:
+    ///
+    /// ```ignore
+    /// const RCC_CFG: RccConfig = RccConfig::new().hse_use(8_000_000).sysclk(72_000_000).checked();
+    /// ```
+    pub const fn checked(self) -> Self {
+        match self.resolve() {
+            Ok(_) => self,
+            Err(err) => panic!("{}", err.as_str()),
+        }
+    }
+
+    /// Clock tree of a valid configuration for const contexts such as baud
+    /// rate tables, panicking (failing the build in a `const`) otherwise.
+    pub const fn clock_checked(self) -> Clock {
+        match self.solve() {
+            Ok(clock) => clock,
+            Err(err) => panic!("{}", err.as_str()),
+        }
+    }
+
+    /// Turns frequency targets into raw PLL and prescaler settings and checks
+    /// every resulting clock against the chip limits.
+    const fn resolve(mut self) -> Result<Self, ClockError> {
+        if self.hse != 0 {
+            let (min, max) = if self.hse_byp {
+                (HSE_BYP_MIN, HSE_BYP_MAX)
+            }
+            else {
+                (HSE_MIN, HSE_MAX)
+            };
+            if self.hse < min || self.hse > max {
+                return Err(ClockError::HseOutOfRange);
+            }
+        }
+
+        if !is_div(&AHB_DIVS, self.ahb_pre)
+            || !is_div(&APB_DIVS, self.apb1_pre as u16)
+            || !is_div(&APB_DIVS, self.apb2_pre as u16)
+            || !is_div(&ADC_DIVS, self.adc_pre as u16) {
+            return Err(ClockError::InvalidPrescaler);
+        }
+        if self.pll != 0 && (self.pll < PLL_MUL_MIN || self.pll > PLL_MUL_MAX) {
+            return Err(ClockError::InvalidPllMul);
+        }
+        if self.prediv == 0 || self.prediv > PREDIV_MAX {
+            return Err(ClockError::InvalidPrescaler);
+        }
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        {
+            if self.prediv2 == 0 || self.prediv2 > 16 {
+                return Err(ClockError::InvalidPrescaler);
+            }
+            if (self.pll2 != 0 && pll23_mul_bits(self.pll2).is_none())
+                || (self.pll3 != 0 && pll23_mul_bits(self.pll3).is_none()) {
+                return Err(ClockError::InvalidPllMul);
+            }
+        }
+
+        if self.usb && self.hse == 0 {
+            return Err(ClockError::UsbClockUnavailable);
+        }
+
+        if self.sysclk != 0 {
+            if self.sysclk > SYSCLK_MAX {
+                return Err(ClockError::SysclkOutOfRange);
+            }
+            self = self.pick_pll();
+        }
+
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        {
+            if (self.pll2 != 0 && self.pll23_out_of_range(self.pll2)) || (self.prediv1_pll2 && self.pll2 == 0) {
+                return Err(ClockError::Pll2OutOfRange);
+            }
+            if self.pll3 != 0 && self.pll23_out_of_range(self.pll3) {
+                return Err(ClockError::Pll3OutOfRange);
+            }
+        }
+
+        if self.pll != 0 && self.hse != 0 {
+            let pll_in = self.pll_src_freq() / self.prediv as u32;
+            if pll_in < PLL_IN_MIN || pll_in > PLL_IN_MAX {
+                return Err(ClockError::PllInputOutOfRange);
+            }
+        }
+
+        let sysclk = self.sysclk_freq();
+        if sysclk == 0 || sysclk > SYSCLK_MAX {
+            return Err(ClockError::SysclkOutOfRange);
+        }
+
+        if self.hclk != 0 {
+            self.ahb_pre = match closest_div(sysclk, self.hclk, sysclk, &AHB_DIVS) {
+                Some(div) => div,
+                None => return Err(ClockError::SysclkOutOfRange),
+            };
+        }
+        let hclk = sysclk / ahb_pre(self.ahb_pre).1;
+        if self.pclk1 != 0 {
+            self.apb1_pre = match closest_div(hclk, self.pclk1, PCLK1_MAX, &APB_DIVS) {
+                Some(div) => div as u8,
+                None => return Err(ClockError::Pclk1OutOfRange),
+            };
+        }
+        if self.pclk2 != 0 {
+            self.apb2_pre = match closest_div(hclk, self.pclk2, PCLK2_MAX, &APB_DIVS) {
+                Some(div) => div as u8,
+                None => return Err(ClockError::Pclk2OutOfRange),
+            };
+        }
+        let pclk2 = hclk / apb_pre(self.apb2_pre).1;
+        if self.adcclk != 0 {
+            self.adc_pre = match closest_div(pclk2, self.adcclk, ADCCLK_MAX, &ADC_DIVS) {
+                Some(div) => div as u8,
+                None => return Err(ClockError::AdcclkOutOfRange),
+            };
+        }
+
+        self.sysclk = 0;
+        self.hclk = 0;
+        self.pclk1 = 0;
+        self.pclk2 = 0;
+        self.adcclk = 0;
+
+        let clock = self.clock();
+        if clock.pclk1 > PCLK1_MAX {
+            return Err(ClockError::Pclk1OutOfRange);
+        }
+        if clock.pclk2 > PCLK2_MAX {
+            return Err(ClockError::Pclk2OutOfRange);
+        }
+        if clock.adcclk > ADCCLK_MAX {
+            return Err(ClockError::AdcclkOutOfRange);
+        }
+        if self.usb && clock.usbclk.is_none() {
+            return Err(ClockError::UsbClockUnavailable);
+        }
+        if self.half_cycle && (self.pll != 0 || self.ahb_pre != 1 || clock.sysclk > HALF_CYCLE_MAX) {
+            return Err(ClockError::FlashAccessInvalid);
+        }
+        #[cfg(not(feature = "stm32f100"))]
+        if !self.prefetch && self.ahb_pre != 1 {
+            return Err(ClockError::FlashAccessInvalid);
+        }
+        Ok(self)
+    }
+
+    /// Picks the PLL setting closest to the SYSCLK target. With HSE configured
+    /// only HSE based sources are considered, otherwise HSI and HSI/2. When
+    /// USB is required only PLL outputs that can be divided to 48 MHz qualify.
+    /// On connectivity line PLL2 is brought in when HSE alone misses the target.
+    const fn pick_pll(mut self) -> Self {
+        let target = self.sysclk;
+        let (direct, (pll_err, mut pll, mut prediv)) = if self.hse != 0 {
+            (self.hse, self.search_pll(self.pll_src_freq(), PREDIV_MAX))
+        }
+        else {
+            (HSI, self.search_pll(HSI / 2, 1))
+        };
+
+        let direct_err = direct.abs_diff(target);
+        let use_direct = !self.usb && direct_err <= pll_err;
+        if use_direct {
+            pll = 0;
+            prediv = 1;
+        }
+
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        {
+            let mut err = if use_direct { direct_err } else { pll_err };
+            if err != 0 && self.hse != 0 && self.pll2 == 0 {
+                // PREDIV2 is shared with PLL3, keep it if PLL3 is in use
+                let (mut prediv2, prediv2_max) = if self.pll3 != 0 {
+                    (self.prediv2, self.prediv2)
+                }
+                else {
+                    (1, 16)
+                };
+                while prediv2 <= prediv2_max {
+                    let mut i = 0;
+                    while i < PLL23_MULS.len() {
+                        let mut cand = self;
+                        cand.prediv2 = prediv2;
+                        cand.pll2 = PLL23_MULS[i];
+                        cand.prediv1_pll2 = true;
+                        let pll2clk = cand.pll2_freq();
+                        let pll2_in = self.hse / prediv2 as u32;
+                        if pll2_in >= PLL23_IN_MIN && pll2_in <= PLL23_IN_MAX
+                            && pll2clk >= PLL23_OUT_MIN && pll2clk <= PLL23_OUT_MAX {
+                            let (cand_err, cand_pll, cand_prediv) = cand.search_pll(pll2clk, PREDIV_MAX);
+                            if cand_err < err {
+                                err = cand_err;
+                                pll = cand_pll;
+                                prediv = cand_prediv;
+                                self.prediv2 = cand.prediv2;
+                                self.pll2 = cand.pll2;
+                                self.prediv1_pll2 = true;
+                            }
+                        }
+                        i += 1;
+                    }
+                    prediv2 += 1;
+                }
+            }
+        }
+
+        self.pll = pll;
+        self.prediv = prediv;
+        self
+    }
+
+    /// Closest PLLMUL and predivider for a PLL fed from `src`, as
+    /// `(error, mul, prediv)`. `mul` is 0 if nothing fits.
+    const fn search_pll(&self, src: u32, prediv_max: u8) -> (u32, u8, u8) {
+        let target = self.sysclk;
+        let mut best = (u32::MAX, 0, 1);
+
+        let mut prediv = 1;
+        while prediv <= prediv_max {
+            let pll_in = src / prediv as u32;
+            if pll_in >= PLL_IN_MIN && pll_in <= PLL_IN_MAX {
+                let mut mul = PLL_MUL_MIN;
+                while mul <= PLL_MUL_MAX {
+                    let freq = src * mul as u32 / prediv as u32;
+                    let err = freq.abs_diff(target);
+                    if freq <= SYSCLK_MAX && err < best.0
+                        && (!self.usb || usb_pre(freq).is_some()) {
+                        best = (err, mul, prediv);
+                    }
+                    mul += 1;
+                }
+            }
+            prediv += 1;
+        }
+        best
+    }
+
+    /// Frequency entering the HSE predivider: HSE, or PLL2 if PREDIV1 is fed from it.
+    const fn pll_src_freq(&self) -> u32 {
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        if self.prediv1_pll2 {
+            return self.pll2_freq();
+        }
+        self.hse
+    }
+
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    const fn pll2_freq(&self) -> u32 {
+        self.hse * self.pll2 as u32 / self.prediv2 as u32
+    }
+
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    const fn pll3_freq(&self) -> u32 {
+        self.hse * self.pll3 as u32 / self.prediv2 as u32
+    }
+
+    /// PLL2/PLL3 input (HSE / PREDIV2) or output with `mul` outside its range.
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    const fn pll23_out_of_range(&self, mul: u8) -> bool {
+        let pll23_in = self.hse / self.prediv2 as u32;
+        let freq = self.hse * mul as u32 / self.prediv2 as u32;
+        pll23_in < PLL23_IN_MIN || pll23_in > PLL23_IN_MAX
+            || freq < PLL23_OUT_MIN || freq > PLL23_OUT_MAX
+    }
+
+    const fn sysclk_freq(&self) -> u32 {
+        match (self.pll, self.hse) {
+            (0, 0) => HSI,
+            (0, hse) => hse,
+            (pll, 0) => HSI / 2 * pll as u32,
+            (pll, _) => self.pll_src_freq() * pll as u32 / self.prediv as u32,
+        }
+    }
+
+    const fn clock(&self) -> Clock {
+        let sysclk = self.sysclk_freq();
+        let hclk = sysclk / ahb_pre(self.ahb_pre).1;
+        let pclk1 = hclk / apb_pre(self.apb1_pre).1;
+        let pclk2 = hclk / apb_pre(self.apb2_pre).1;
+        let adcclk = pclk2 / adc_pre(self.adc_pre).1;
+
+        // Timers run at twice PCLK whenever their APB prescaler is not 1
+        let timclk1 = if self.apb1_pre == 1 { pclk1 } else { pclk1 * 2 };
+        let timclk2 = if self.apb2_pre == 1 { pclk2 } else { pclk2 * 2 };
+
+        let usbclk = if cfg!(any(feature = "stm32f103", feature = "stm32f105", feature = "stm32f107"))
+            && self.pll != 0 && self.hse != 0 && usb_pre(sysclk).is_some() {
+            Some(USBCLK)
+        }
+        else {
+            None
+        };
+
+        Clock {
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            timclk1,
+            timclk2,
+            adcclk,
+            usbclk,
+            flash_latency: flash_latency(hclk),
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            pll2clk: if self.pll2 != 0 { Some(self.pll2_freq()) } else { None },
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            pll3clk: if self.pll3 != 0 { Some(self.pll3_freq()) } else { None },
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            i2s2clk: if self.i2s2_pll3 { 2 * self.pll3_freq() } else { sysclk },
+            #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+            i2s3clk: if self.i2s3_pll3 { 2 * self.pll3_freq() } else { sysclk },
+        }
+    }
+
+    /// Applies the configuration, panicking if it cannot be reached. May be
+    /// called again at runtime to change frequency; a running SysTime is
+    /// reloaded for the new HCLK.
     #[inline(always)]
     pub fn tune(self) -> Clock {
+        self.try_tune().unwrap()
+    }
+
+    /// Applies the configuration. With `hsi_fallback()` a HSE startup failure
+    /// is retried with the same SYSCLK target on HSI. A running HSE that is
+    /// not configured is stopped, unless it clocks the RTC or the MCO pin.
+    #[inline(always)]
+    pub fn try_tune(self) -> Result<Clock, ClockError> {
+        let cfg = self.resolve()?;
+        // CSS is switched on at the end of apply and its NMI must find the
+        // config by then. It is switched off while the config is written.
+        unsafe { (*RCC::ptr()).cr.modify(|_, w| w.csson().clear_bit()) };
+        unsafe { G_CSS_CONFIG = if self.css { Some(self) } else { None } };
+        let clock = match cfg.apply() {
+            Err(ClockError::HseTimeout) if self.hsi_fallback => self.on_hsi()?.apply()?,
+            res => res?,
+        };
+        Ok(clock)
+    }
+
+    /// The same targets resolved for HSI, used when HSE is unavailable.
+    fn on_hsi(self) -> Result<Self, ClockError> {
+        let mut hsi = self;
+        hsi.sysclk = self.resolve()?.sysclk_freq();
+        hsi.hse = 0;
+        hsi.hse_byp = false;
+        hsi.css = false;
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        {
+            hsi.prediv1_pll2 = false;
+            hsi.pll2 = 0;
+            hsi.pll3 = 0;
+            hsi.i2s2_pll3 = false;
+            hsi.i2s3_pll3 = false;
+        }
+        hsi.resolve()
+    }
+
+    fn apply(self) -> Result<Clock, ClockError> {
         let rcc = unsafe { &*RCC::ptr() };
         let flash = unsafe { &*FLASH::ptr()};
-        let mut trash_hold: u16 = 0;
+        let cfg = self;
+        let clock = cfg.clock();
+        if !SysTime::tick_fits(clock.hclk) {
+            return Err(ClockError::SysTickOutOfRange);
+        }
+
+        // Run from HSI while the PLLs and prescalers are reprogrammed. HSI is
+        // below 24 MHz, so the flash latency can then be set for the target
+        // before switching to it, whether it is faster or slower.
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+        if let Some(trim) = cfg.hsi_trim {
+            calibrate::set_hsi_trim(trim);
+        }
+        unsafe { rcc.cfgr.modify(|_, w| w.sw().bits(0b0)) };
+        while rcc.cfgr.read().sws().bits() != 0b0 {}
 
-        let sh = if self.hse != 0 {
-            if self.hse_byp == true {
+        // Prefetch may only be switched below 24 MHz with HCLK undivided, and
+        // half-cycle access has to be off before SYSCLK rises again.
+        unsafe { rcc.cfgr.modify(|_, w| w.hpre().bits(0b0000)) };
+        SysTime::reload(HSI);
+        flash.acr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 3)) });
+        #[cfg(not(feature = "stm32f100"))]
+        flash.acr.modify(|r, w| unsafe { w.bits((r.bits() & !(1 << 4)) | ((cfg.prefetch as u32) << 4)) });
+
+        rcc.cr.modify(|_, w| w.csson().clear_bit().pllon().clear_bit());
+        while rcc.cr.read().pllrdy().bit_is_set() {}
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        {
+            rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !((1 << 26) | (1 << 28))) });
+            while rcc.cr.read().bits() & ((1 << 27) | (1 << 29)) != 0 {}
+        }
+
+        // HSE still clocking the RTC or MCO is kept when no longer configured
+        let rtcsel = (rcc.bdcr.read().bits() >> 8) & 0b11;
+        let mco = (rcc.cfgr.read().bits() >> 24) & MCO_MASK;
+        let hse_used = rtcsel == RtcClock::HseDiv128 as u32 || mco == Mco::Hse as u32;
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        let hse_used = hse_used || mco == Mco::Xt1 as u32;
+
+        let cr = rcc.cr.read();
+        if cr.hseon().bit_is_set() && ((cfg.hse == 0 && !hse_used) || (cfg.hse != 0 && cr.hsebyp().bit() != cfg.hse_byp)) {
+            rcc.cr.modify(|_, w| w.hseon().clear_bit());
+            while rcc.cr.read().hserdy().bit_is_set() {}
+            rcc.cr.modify(|_, w| w.hsebyp().clear_bit());
+        }
+
+        if cfg.hse != 0 && rcc.cr.read().hserdy().bit_is_clear() {
+            if cfg.hse_byp {
                 rcc.cr.modify(|_, w|w.hsebyp().set_bit());
             }
             rcc.cr.modify(|_, w|w.hseon().set_bit());
-            
-            while rcc.cr.read().hserdy().bit_is_clear() {
 
-                trash_hold += 1;
-                if trash_hold == 1000 {
-                    rcc.cr.modify(|_, w|w.hseon().clear_bit());
-                    panic!();
+            if !wait_ready(cfg.hse_timeout, || rcc.cr.read().hserdy().bit_is_set()) {
+                rcc.cr.modify(|_, w|w.hseon().clear_bit().hsebyp().clear_bit());
+                return Err(ClockError::HseTimeout);
+            }
+        }
+
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        {
+            rcc.cfgr2.write(|w| unsafe { w.bits(
+                (cfg.prediv as u32 - 1)
+                | ((cfg.prediv2 as u32 - 1) << 4)
+                | ((pll23_mul_bits(cfg.pll2).unwrap_or(0) as u32) << 8)
+                | ((pll23_mul_bits(cfg.pll3).unwrap_or(0) as u32) << 12)
+                | ((cfg.prediv1_pll2 as u32) << 16)
+                | ((cfg.i2s2_pll3 as u32) << 17)
+                | ((cfg.i2s3_pll3 as u32) << 18)
+            ) });
+
+            if cfg.pll2 != 0 {
+                rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() | (1 << 26)) }); // PLL2ON
+                if !wait_ready(PLL_LOCK_TIMEOUT, || rcc.cr.read().bits() & (1 << 27) != 0) {
+                    rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 26)) });
+                    return Err(ClockError::PllLockTimeout);
                 }
             }
-            self.hse
-        } 
-        else {
-            if self.pll == 0{
-                HSI
+            if cfg.pll3 != 0 {
+                rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() | (1 << 28)) }); // PLL3ON
+                if !wait_ready(PLL_LOCK_TIMEOUT, || rcc.cr.read().bits() & (1 << 29) != 0) {
+                    rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << 28)) });
+                    return Err(ClockError::PllLockTimeout);
+                }
             }
-            else {
-                HSI / 2
+        }
+
+        if cfg.pll != 0 {
+            // PLLMUL is mul - 2 on all parts, but only a safe field off connectivity line
+            rcc.cfgr.modify(|r, w| unsafe { w.bits((r.bits() & !(0b1111 << 18)) | (((cfg.pll - 2) as u32) << 18)) });
+            rcc.cfgr.modify(|_, w| w.pllsrc().bit(cfg.hse != 0));
+            // With PREDIV1 (value and connectivity line) PLLXTPRE mirrors PREDIV1[0]
+            #[cfg(not(any(feature = "stm32f100", feature = "stm32f105", feature = "stm32f107")))]
+            rcc.cfgr.modify(|_, w| w.pllxtpre().bit(cfg.prediv == 2));
+            #[cfg(feature = "stm32f100")]
+            rcc.cfgr2.write(|w| unsafe { w.bits(cfg.prediv as u32 - 1) });
+            // USBPRE on F103, OTGFSPRE on connectivity line
+            #[cfg(any(feature = "stm32f103", feature = "stm32f105", feature = "stm32f107"))]
+            if let Some(bit) = usb_pre(clock.sysclk) {
+                rcc.cfgr.modify(|r, w| unsafe { w.bits((r.bits() & !(1 << 22)) | ((bit as u32) << 22)) });
             }
-        };
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
 
-        let sysclk = 
-            if self.pll != 0 {
-                let sysclk = self.pll as u32 * sh;
-                
-                assert!(sysclk <= 72_000_000);
-                rcc.cfgr.modify(|_, w|
-                    w.pllmul().bits((self.pll - 2) as u8)
-                    .pllsrc().bit(if self.hse != 0 {
-                        true
-                    }
-                    else {
-                        false
-                    })
-                );
-                rcc.cr.modify(|_, w| w.pllon().set_bit());
-                
-                while rcc.cr.read().pllrdy().bit_is_clear() {}
-                sysclk
+            if !wait_ready(PLL_LOCK_TIMEOUT, || rcc.cr.read().pllrdy().bit_is_set()) {
+                rcc.cr.modify(|_, w| w.pllon().clear_bit());
+                return Err(ClockError::PllLockTimeout);
             }
-            else {
-                sh
-            };
+        }
 
-        let (apb1_pre_bits, pclk1) = match self.apb1_pre {
-            2 => (0b100, sysclk / 2),
-            4 => (0b101, sysclk / 4),
-            8 => (0b110, sysclk / 8),
-            16 => (0b111, sysclk / 16),
-            _ => (0b0, sysclk),
-        };
-        assert!(pclk1 <= 36_000_000);
-        
-        let (apb2_pre_bits, pclk2) = match self.apb2_pre {
-            2 => (0b100, sysclk / 2),
-            4 => (0b101, sysclk / 4),
-            8 => (0b110, sysclk / 8),
-            16 => (0b111, sysclk / 16),
-            _ => (0b0, sysclk),
+        let ahb_pre_bits = ahb_pre(cfg.ahb_pre).0;
+        let apb1_pre_bits = apb_pre(cfg.apb1_pre).0;
+        let apb2_pre_bits = apb_pre(cfg.apb2_pre).0;
+        let adc_pre_bits = adc_pre(cfg.adc_pre).0;
+        let sw_bits = if cfg.pll != 0 {
+            0b10 // PLL
+        }
+        else if cfg.hse != 0 {
+            0b1 // HSE
+        }
+        else {
+            0b0 // HSI
         };
 
-        let adc_pre_bits = match self.adc_pre {
-            2 => 0b00,
-            4 => 0b01,
-            6 => 0b10,
-            8 => 0b11,
-            _ => 0b10,
-        };
-        let adc_clk = pclk2 / adc_pre_bits;
-        assert!(adc_clk <= 14_000_000);
+        // Value line flash runs without wait states, LATENCY is reserved there
+        #[cfg(not(feature = "stm32f100"))]
+        flash.acr.modify(|_, w| unsafe { w.latency().bits(flash_latency(clock.hclk)) });
 
         unsafe {
-            flash.acr.modify(|_, w|
-                w.latency().bits( if sysclk <= 24_000_000 {
-                        0b000
-                    } 
-                    else if sysclk <= 48_000_000 {
-                        0b001
-                    }
-                    else {
-                        0b010
-                    }));
-
             rcc.cfgr.modify(|_, w|
-                w.adcpre().bits(adc_pre_bits as u8)
+                w.adcpre().bits(adc_pre_bits)
+                .hpre().bits(ahb_pre_bits)
                 .ppre1().bits(apb1_pre_bits)
                 .ppre2().bits(apb2_pre_bits)
-                .sw().bits( if self.pll != 0 {
-                    0b10 // PLL
-                } 
-                else if self.hse != 0 {
-                    0b1 // HSE
-                }
-                else {
-                    0b0 // HSI
-                })
             );
+            rcc.cfgr.modify(|_, w| w.sw().bits(sw_bits));
+        }
+        while rcc.cfgr.read().sws().bits() != sw_bits {}
+        if cfg.half_cycle {
+            flash.acr.modify(|r, w| unsafe { w.bits(r.bits() | (1 << 3)) });
+        }
+
+        rcc.cr.modify(|_, w| w.csson().bit(cfg.css && cfg.hse != 0));
+        if cfg.hse != 0 {
+            unsafe { G_HSE = cfg.hse };
+        }
+        SysTime::reload(clock.hclk);
+
+        Ok(clock)
+    }
+}
+
+/// Reset flags latched in RCC_CSR. Several can be set at once, e.g. a
+/// power-on reset also reports a pin reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetReason {
+    pub pin: bool,
+    pub power_on: bool,
+    pub software: bool,
+    pub independent_watchdog: bool,
+    pub window_watchdog: bool,
+    pub low_power: bool,
+}
+impl ResetReason {
+    /// Reads the reset flags without clearing them.
+    pub fn read() -> ResetReason {
+        let csr = unsafe { (*RCC::ptr()).csr.read() };
+        ResetReason {
+            pin: csr.pinrstf().bit_is_set(),
+            power_on: csr.porrstf().bit_is_set(),
+            software: csr.sftrstf().bit_is_set(),
+            independent_watchdog: csr.iwdgrstf().bit_is_set(),
+            window_watchdog: csr.wwdgrstf().bit_is_set(),
+            low_power: csr.lpwrrstf().bit_is_set(),
+        }
+    }
+
+    /// Reads the reset flags and clears them with RMVF, so the next boot only
+    /// reports its own cause. Call once at startup, before `RccConfig::tune`.
+    pub fn take() -> ResetReason {
+        let reason = ResetReason::read();
+        unsafe { (*RCC::ptr()).csr.modify(|_, w| w.rmvf().set_bit()) };
+        reason
+    }
+
+    /// Only the NRST pin was asserted, no other cause is recorded.
+    pub fn is_external(&self) -> bool {
+        self.pin && !self.power_on && !self.software && !self.independent_watchdog
+            && !self.window_watchdog && !self.low_power
+    }
+
+    pub fn is_watchdog(&self) -> bool {
+        self.independent_watchdog || self.window_watchdog
+    }
+}
+
+/// Registers a function called from the NMI handler after a HSE failure has
+/// been detected by the clock security system and HSI has taken over.
+pub fn on_hse_failure(callback: fn(&Clock)) {
+    cortex_m::interrupt::free(|_| unsafe { G_CSS_CALLBACK = Some(callback) });
+}
+
+#[cfg(feature = "css-nmi")]
+#[exception]
+unsafe fn NonMaskableInt() {
+    unsafe { css_nmi_handler() };
+}
+
+/// Handles a HSE failure flagged by the clock security system, see
+/// `RccConfig::css`. Does nothing if CSS has not raised the NMI.
+///
+/// SysTime is reloaded for the new HCLK. Critical sections do not mask the
+/// NMI, so a failure hitting a SysTick update or a `SysTime::config` call
+/// can leave the SysTime count wrong.
+///
+/// # Safety
+///
+/// - Call only from the NMI handler
+pub unsafe fn css_nmi_handler() {
+    let rcc = unsafe { &*RCC::ptr() };
+    if rcc.cir.read().cssf().bit_is_clear() {
+        return;
+    }
+    rcc.cir.modify(|_, w| w.cssc().set_bit());
+
+    // Hardware has already switched SYSCLK to HSI and stopped HSE and the PLL
+    let cfg = unsafe { G_CSS_CONFIG };
+    unsafe { G_CSS_CONFIG = None };
+    // Never panic in the NMI: if no HSI setup can be applied, stay on plain HSI
+    let clock = match cfg.map(|cfg| cfg.on_hsi().and_then(|hsi| hsi.apply())) {
+        Some(Ok(clock)) => clock,
+        _ => RCC::rcc_config().try_tune().unwrap_or_else(|_| Clock::current()),
+    };
+
+    if let Some(callback) = unsafe { G_CSS_CALLBACK } {
+        callback(&clock);
+    }
+}
+
+/// Returns the clock tree to its reset state: SYSCLK from untrimmed HSI,
+/// PLLs, HSE and CSS off, prescalers and flash latency cleared and all
+/// peripheral clocks but SRAM and flash disabled. The backup domain (RTC, LSE)
+/// is left alone. Call `SysTime::deinit()` too before handing over to an
+/// application.
+pub fn deinit() {
+    let rcc = unsafe { &*RCC::ptr() };
+    let flash = unsafe { &*FLASH::ptr() };
+
+    rcc.cr.modify(|_, w| w.hsion().set_bit());
+    while rcc.cr.read().hsirdy().bit_is_clear() {}
+    calibrate::set_hsi_trim(16);
+    unsafe { rcc.cfgr.modify(|_, w| w.sw().bits(0b0)) };
+    while rcc.cfgr.read().sws().bits() != 0b0 {}
+    rcc.cfgr.reset();
+
+    rcc.cr.modify(|_, w| w.csson().clear_bit().pllon().clear_bit());
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    rcc.cr.modify(|r, w| unsafe { w.bits(r.bits() & !((1 << 26) | (1 << 28))) });
+    while rcc.cr.read().bits() & ((1 << 25) | (1 << 27) | (1 << 29)) != 0 {}
+    rcc.cr.modify(|_, w| w.hseon().clear_bit());
+    while rcc.cr.read().hserdy().bit_is_set() {}
+    rcc.cr.modify(|_, w| w.hsebyp().clear_bit());
+
+    #[cfg(any(feature = "stm32f100", feature = "stm32f105", feature = "stm32f107"))]
+    rcc.cfgr2.reset();
+
+    // Disable all clock interrupts and clear their flags, CSSC included
+    #[cfg(not(any(feature = "stm32f105", feature = "stm32f107")))]
+    rcc.cir.write(|w| unsafe { w.bits(0x009F_0000) });
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    rcc.cir.write(|w| unsafe { w.bits(0x00FF_0000) });
+
+    flash.acr.reset();
+
+    rcc.ahbenr.write(|w| unsafe { w.bits(0x0000_0014) });
+    rcc.apb1enr.reset();
+    rcc.apb2enr.reset();
+
+    unsafe {
+        G_HSE = 0;
+        G_CSS_CONFIG = None;
+    }
+    SysTime::reload(HSI);
+}
+
+/// Polls `ready` until it returns true or `timeout_ms` elapses, measured with
+/// the DWT cycle counter at the current AHB clock.
+fn wait_ready(timeout_ms: u32, ready: impl Fn() -> bool) -> bool {
+    SysTime::dwt_enable();
+    let timeout = timeout_ms.saturating_mul(SysTime::ahb_hz() / 1000);
+    let start = SysTime::dwt_now();
+    while !ready() {
+        if SysTime::dwt_now().wrapping_sub(start) >= timeout {
+            return false;
         }
+    }
+    true
+}
+
+/// Flash wait states for HCLK: 0 up to 24 MHz, 1 up to 48 MHz, 2 above.
+#[cfg(not(feature = "stm32f100"))]
+const fn flash_latency(hclk: u32) -> u8 {
+    if hclk <= 24_000_000 {
+        0
+    }
+    else if hclk <= 48_000_000 {
+        1
+    }
+    else {
+        2
+    }
+}
+
+#[cfg(feature = "stm32f100")]
+const fn flash_latency(_hclk: u32) -> u8 {
+    0
+}
+
+/// HPRE bits and divider for an AHB prescaler.
+const fn ahb_pre(div: u16) -> (u8, u32) {
+    match div {
+        2 => (0b1000, 2),
+        4 => (0b1001, 4),
+        8 => (0b1010, 8),
+        16 => (0b1011, 16),
+        64 => (0b1100, 64),
+        128 => (0b1101, 128),
+        256 => (0b1110, 256),
+        512 => (0b1111, 512),
+        _ => (0b0, 1),
+    }
+}
+
+/// PPRE bits and divider for an APB prescaler.
+const fn apb_pre(div: u8) -> (u8, u32) {
+    match div {
+        2 => (0b100, 2),
+        4 => (0b101, 4),
+        8 => (0b110, 8),
+        16 => (0b111, 16),
+        _ => (0b0, 1),
+    }
+}
+
+/// ADCPRE bits and divider for an ADC prescaler.
+const fn adc_pre(div: u8) -> (u8, u32) {
+    match div {
+        2 => (0b00, 2),
+        4 => (0b01, 4),
+        8 => (0b11, 8),
+        _ => (0b10, 6),
+    }
+}
+
+/// APB divider for PPRE bits.
+fn apb_div(bits: u32) -> u8 {
+    match bits {
+        0b100 => 2,
+        0b101 => 4,
+        0b110 => 8,
+        0b111 => 16,
+        _ => 1,
+    }
+}
+
+/// PLL2/PLL3 multiplier for PLL2MUL/PLL3MUL bits.
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+fn pll23_mul(bits: u32) -> u8 {
+    match bits {
+        0b0110..=0b1100 => bits as u8 + 2,
+        0b1110 => 16,
+        0b1111 => 20,
+        _ => 0,
+    }
+}
+
+/// PLL2MUL/PLL3MUL bits for a multiplier.
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+const fn pll23_mul_bits(mul: u8) -> Option<u8> {
+    match mul {
+        8..=14 => Some(mul - 2),
+        16 => Some(0b1110),
+        20 => Some(0b1111),
+        _ => None,
+    }
+}
+
+/// USBPRE bit giving 48 MHz from the PLL output, if any.
+const fn usb_pre(pllclk: u32) -> Option<u8> {
+    match pllclk {
+        48_000_000 => Some(1), // PLL / 1
+        72_000_000 => Some(0), // PLL / 1.5
+        _ => None,
+    }
+}
+
+/// Divider from `divs` that brings `src` closest to `target` without exceeding `max`.
+const fn closest_div(src: u32, target: u32, max: u32, divs: &[u16]) -> Option<u16> {
+    let mut best = None;
+    let mut best_err = u32::MAX;
+    let mut i = 0;
+    while i < divs.len() {
+        let freq = src / divs[i] as u32;
+        let err = freq.abs_diff(target);
+        if freq <= max && err < best_err {
+            best = Some(divs[i]);
+            best_err = err;
+        }
+        i += 1;
+    }
+    best
+}
 
-        Clock { 
-            sysclk:(sysclk),
-            pclk1: (pclk1), 
-            pclk2: (pclk2), 
-            adcclk: (adc_clk)
+const fn is_div(divs: &[u16], div: u16) -> bool {
+    let mut i = 0;
+    while i < divs.len() {
+        if divs[i] == div {
+            return true;
         }
+        i += 1;
     }
+    false
 }
 
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Clock {
     pub sysclk: u32,
+    pub hclk: u32,
     pub pclk1: u32,
     pub pclk2: u32,
+    pub timclk1: u32,
+    pub timclk2: u32,
     pub adcclk: u32,
+    /// 48 MHz USB clock, `None` if the PLL runs from HSI or cannot provide it.
+    pub usbclk: Option<u32>,
+    /// Flash wait states for HCLK, always 0 on value line.
+    pub flash_latency: u8,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub pll2clk: Option<u32>,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub pll3clk: Option<u32>,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub i2s2clk: u32,
+    #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+    pub i2s3clk: u32,
+}
+
+impl Clock {
+    /// Decodes the running clock tree from RCC registers, using the HSE
+    /// frequency of the last `tune()`. See `current_with_hse`.
+    pub fn current() -> Clock {
+        Clock::current_with_hse(unsafe { G_HSE })
+    }
+
+    /// Decodes the running clock tree from RCC registers, e.g. after a
+    /// bootloader handoff. `hse` is the crystal frequency, which cannot be
+    /// read back; if HSE drives SYSCLK and `hse` is 0, or PLLMUL holds a
+    /// reserved value, all clocks read as 0. Flash wait states come from FLASH_ACR.
+    pub fn current_with_hse(hse: u32) -> Clock {
+        let rcc = unsafe { &*RCC::ptr() };
+        let cfgr = rcc.cfgr.read().bits();
+
+        let mut cfg = RCC::rcc_config();
+        cfg.ahb_pre = match (cfgr >> 4) & 0b1111 {
+            0b1000 => 2,
+            0b1001 => 4,
+            0b1010 => 8,
+            0b1011 => 16,
+            0b1100 => 64,
+            0b1101 => 128,
+            0b1110 => 256,
+            0b1111 => 512,
+            _ => 1,
+        };
+        cfg.apb1_pre = apb_div((cfgr >> 8) & 0b111);
+        cfg.apb2_pre = apb_div((cfgr >> 11) & 0b111);
+        cfg.adc_pre = [2, 4, 6, 8][((cfgr >> 14) & 0b11) as usize];
+
+        let pll_hse = cfgr & (1 << 16) != 0;
+        match (cfgr >> 2) & 0b11 {
+            0b01 => cfg.hse = hse,
+            0b10 => {
+                let mul = ((cfgr >> 18) & 0b1111) as u8;
+                // x2..x16, 0b1111 is x16 as well
+                #[cfg(not(any(feature = "stm32f105", feature = "stm32f107")))]
+                {
+                    cfg.pll = (mul + 2).min(16);
+                }
+                // x4..x9, 0b1101 is x6.5, taken as x13 with PREDIV1 doubled below
+                #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+                match mul {
+                    0b0010..=0b0111 => cfg.pll = mul + 2,
+                    0b1101 => cfg.pll = 13,
+                    _ => return Clock::default(),
+                }
+                if pll_hse {
+                    cfg.hse = hse;
+                }
+            }
+            _ => {}
+        }
+        if cfg.hse == 0 && ((cfgr >> 2) & 0b11 == 0b01 || (cfg.pll != 0 && pll_hse)) {
+            return Clock::default();
+        }
+
+        cfg.prediv = if cfgr & (1 << 17) != 0 { 2 } else { 1 };
+        #[cfg(feature = "stm32f100")]
+        {
+            cfg.prediv = (rcc.cfgr2.read().bits() & 0b1111) as u8 + 1;
+        }
+        #[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+        {
+            let cr = rcc.cr.read().bits();
+            let cfgr2 = rcc.cfgr2.read().bits();
+            cfg.prediv = (cfgr2 & 0b1111) as u8 + 1;
+            cfg.prediv2 = ((cfgr2 >> 4) & 0b1111) as u8 + 1;
+            cfg.prediv1_pll2 = cfgr2 & (1 << 16) != 0;
+            cfg.i2s2_pll3 = cfgr2 & (1 << 17) != 0;
+            cfg.i2s3_pll3 = cfgr2 & (1 << 18) != 0;
+            if cr & (1 << 26) != 0 {
+                cfg.pll2 = pll23_mul((cfgr2 >> 8) & 0b1111);
+            }
+            if cr & (1 << 28) != 0 {
+                cfg.pll3 = pll23_mul((cfgr2 >> 12) & 0b1111);
+            }
+            if cfg.pll == 13 {
+                cfg.prediv *= 2;
+            }
+        }
+
+        let mut clock = cfg.clock();
+        #[cfg(not(feature = "stm32f100"))]
+        {
+            clock.flash_latency = (unsafe { &*FLASH::ptr() }.acr.read().bits() & 0b111) as u8;
+        }
+        let usbpre = ((cfgr >> 22) & 1) as u8;
+        if usb_pre(clock.sysclk) != Some(usbpre) {
+            clock.usbclk = None;
+        }
+        clock
+    }
+
+    /// Kernel clock of the given timer instance, e.g. `clock.timclk::<TIM2>()`.
+    pub fn timclk<TIM: TimerClock>(&self) -> u32 {
+        TIM::timer_clock(self)
+    }
+}
+
+/// Timer peripherals and the APB timer clock they are fed from.
+pub trait TimerClock {
+    fn timer_clock(clock: &Clock) -> u32;
+}
+
+macro_rules! timer_clock {
+    ($timclkx:ident: $($TIMx:ident),+) => {
+        $(
+            impl TimerClock for crate::pac::$TIMx {
+                fn timer_clock(clock: &Clock) -> u32 {
+                    clock.$timclkx
+                }
+            }
+        )+
+    };
+}
+
+timer_clock!(timclk1: TIM2, TIM3, TIM4, TIM5, TIM6, TIM7);
+
+#[cfg(not(feature = "stm32f101"))]
+timer_clock!(timclk2: TIM1);
+
+#[cfg(feature = "stm32f103")]
+timer_clock!(timclk2: TIM8);
+
+#[cfg(any(feature = "stm32f101", feature = "stm32f103"))]
+timer_clock!(timclk2: TIM9, TIM10, TIM11);
+
+#[cfg(any(feature = "stm32f100", feature = "stm32f101", feature = "stm32f103"))]
+timer_clock!(timclk1: TIM12, TIM13, TIM14);
+
+#[cfg(feature = "stm32f100")]
+timer_clock!(timclk2: TIM15, TIM16, TIM17);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "stm32f103", feature = "stm32f105", feature = "stm32f107"))]
+    #[test]
+    fn hse_8mhz_to_72mhz_with_usb() {
+        let cfg = RCC::rcc_config().hse_use(8_000_000).sysclk(72_000_000).usb().resolve().unwrap();
+        assert_eq!(cfg.pll, 9);
+        let clock = cfg.clock();
+        assert_eq!(clock.sysclk, 72_000_000);
+        assert_eq!(clock.pclk1, 36_000_000);
+        assert_eq!(clock.usbclk, Some(48_000_000));
+    }
+
+    #[cfg(feature = "stm32f103")]
+    #[test]
+    fn hsi_only_to_64mhz() {
+        let clock = RCC::rcc_config().sysclk(64_000_000).solve().unwrap();
+        assert_eq!(clock.sysclk, 64_000_000);
+        assert_eq!(clock.flash_latency, 2);
+    }
+
+    #[cfg(feature = "stm32f103")]
+    #[test]
+    fn usb_needs_hse() {
+        let clock = RCC::rcc_config().sysclk(48_000_000).solve().unwrap();
+        assert_eq!(clock.usbclk, None);
+        let clock = RCC::rcc_config().sysclk(48_000_000).usb().solve();
+        assert_eq!(clock.unwrap_err(), ClockError::UsbClockUnavailable);
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "stm32f105", feature = "stm32f107"))]
+    #[test]
+    fn apb1_undivided_at_72mhz() {
+        let clock = RCC::rcc_config().hse_use(8_000_000).sysclk(72_000_000).apb1(1).solve();
+        assert_eq!(clock.unwrap_err(), ClockError::Pclk1OutOfRange);
+    }
+
+    #[cfg(feature = "stm32f100")]
+    #[test]
+    fn value_line_limited_to_24mhz() {
+        let clock = RCC::rcc_config().hse_use(8_000_000).sysclk(36_000_000).solve();
+        assert_eq!(clock.unwrap_err(), ClockError::SysclkOutOfRange);
+    }
+
+    #[cfg(feature = "stm32f101")]
+    #[test]
+    fn access_line_at_36mhz() {
+        let clock = RCC::rcc_config().sysclk(36_000_000).solve().unwrap();
+        assert_eq!(clock.sysclk, 36_000_000);
+        assert_eq!(clock.flash_latency, 1);
+    }
+
+    #[test]
+    fn sysclk_above_limit() {
+        let clock = RCC::rcc_config().hse_use(8_000_000).sysclk(100_000_000).solve();
+        assert_eq!(clock.unwrap_err(), ClockError::SysclkOutOfRange);
+    }
 }
@@ -0,0 +1,151 @@
+use super::{Clock, ClockError, HSI, LSE};
+use crate::pac::{BKP, PWR, RCC, RTC};
+use crate::rcc::Enable;
+use crate::systime::SysTime;
+
+// One HSITRIM step moves HSI by roughly 40 kHz
+const HSI_TRIM_STEP: u32 = 40_000;
+const HSI_TRIM_MAX: u8 = 31;
+const HSI_TRIM_PASSES: u8 = 4;
+// Highest external reference whose edges polling still catches at 8 MHz HCLK
+const EXTERNAL_REF_MAX: u32 = 10_000;
+
+/// Result of an HSI calibration run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HsiCalibration {
+    /// HSITRIM value left in RCC_CR, pass it to `RccConfig::hsi_trim` on later boots.
+    pub trim: u8,
+    /// Measured HSI frequency with that trim.
+    pub hsi: u32,
+    /// Remaining HSI error in parts per million.
+    pub error_ppm: i32,
+}
+
+/// Trims HSI against LSE. LSE has to be running as RTC clock
+/// (`RtcConfig::lse_use`) and SYSCLK has to come from HSI, directly or through
+/// the PLL. Each pass counts core cycles over 4096 LSE periods (~125 ms)
+/// using the RTC prescaler counter, so the RTC time itself is not disturbed.
+pub fn calibrate_hsi_lse() -> Result<HsiCalibration, ClockError> {
+    let rcc = unsafe { &*RCC::ptr() };
+    let bdcr = rcc.bdcr.read();
+    if bdcr.lserdy().bit_is_clear() || (bdcr.bits() >> 8) & 0b11 != 0b01 || bdcr.rtcen().bit_is_clear() {
+        return Err(ClockError::LseNotRtcClock);
+    }
+
+    PWR::enable();
+    BKP::enable();
+    let rtc = unsafe { &*RTC::ptr() };
+    rtc.crl.modify(|_, w| w.rsf().clear_bit());
+    while rtc.crl.read().rsf().bit_is_clear() {}
+
+    // The prescaler counter steps once per LSE period
+    calibrate(|| measure_hclk(4096, LSE, || (rtc.divh.read().bits() << 16) | rtc.divl.read().bits()))
+}
+
+/// Trims HSI against a periodic signal of `freq` Hz sampled by `is_high`,
+/// e.g. `PORTA::A0::is_high` wired to a GPS PPS output or a signal generator.
+/// `freq` has to be 1 Hz..10 kHz, faster edges would be missed by polling.
+/// SYSCLK has to come from HSI. Each pass spans about 100 ms of the reference,
+/// but at least one period, so 1 s with a 1 Hz PPS.
+pub fn calibrate_hsi_external(freq: u32, is_high: impl Fn() -> bool) -> Result<HsiCalibration, ClockError> {
+    if freq == 0 || freq > EXTERNAL_REF_MAX {
+        return Err(ClockError::ReferenceOutOfRange);
+    }
+    // Both edges are counted, a whole number of periods keeps duty cycle out of it
+    let periods = (freq / 10).max(1);
+    calibrate(|| measure_hclk(2 * periods, 2 * freq, || is_high() as u32))
+}
+
+/// Runs up to `HSI_TRIM_PASSES` trim corrections and leaves the best trim
+/// found in RCC_CR. `measure` returns the measured HCLK in Hz.
+fn calibrate(measure: impl Fn() -> Result<u32, ClockError>) -> Result<HsiCalibration, ClockError> {
+    let rcc = unsafe { &*RCC::ptr() };
+    let cfgr = rcc.cfgr.read().bits();
+    let on_hsi = match (cfgr >> 2) & 0b11 {
+        0b00 => true,
+        0b10 => cfgr & (1 << 16) == 0,
+        _ => false,
+    };
+    if !on_hsi {
+        return Err(ClockError::HsiNotInUse);
+    }
+    let hclk = Clock::current().hclk as u64;
+
+    // HCLK scales with HSI, so HSI = HSI_nominal * HCLK_measured / HCLK_nominal
+    let measure_hsi = || -> Result<u32, ClockError> {
+        Ok((HSI as u64 * measure()? as u64 / hclk) as u32)
+    };
+
+    let mut trim = hsi_trim();
+    let mut hsi = measure_hsi()?;
+    let mut best = (trim, hsi);
+    let mut pass = 0;
+    while pass < HSI_TRIM_PASSES {
+        let diff = HSI as i32 - hsi as i32;
+        let steps = (diff + diff.signum() * HSI_TRIM_STEP as i32 / 2) / HSI_TRIM_STEP as i32;
+        let next = (trim as i32 + steps).clamp(0, HSI_TRIM_MAX as i32) as u8;
+        if next == trim {
+            break;
+        }
+        trim = next;
+        set_hsi_trim(trim);
+        hsi = measure_hsi()?;
+        if hsi.abs_diff(HSI) < best.1.abs_diff(HSI) {
+            best = (trim, hsi);
+        }
+        pass += 1;
+    }
+    if best.0 != trim {
+        set_hsi_trim(best.0);
+    }
+
+    Ok(HsiCalibration {
+        trim: best.0,
+        hsi: best.1,
+        error_ppm: ((best.1 as i64 - HSI as i64) * 1_000_000 / HSI as i64) as i32,
+    })
+}
+
+/// Counts DWT cycles over `changes` changes of `sample`, which changes `rate`
+/// times per second, and returns the resulting core clock. Gives up if a
+/// single change takes longer than ten nominal periods.
+fn measure_hclk(changes: u32, rate: u32, sample: impl Fn() -> u32) -> Result<u32, ClockError> {
+    SysTime::dwt_enable();
+    let limit = (SysTime::ahb_hz() as u64 * 10 / rate as u64).min(u32::MAX as u64) as u32;
+
+    let wait_change = || {
+        let start = SysTime::dwt_now();
+        let last = sample();
+        while sample() == last {
+            if SysTime::dwt_now().wrapping_sub(start) > limit {
+                return false;
+            }
+        }
+        true
+    };
+
+    if !wait_change() {
+        return Err(ClockError::ReferenceTimeout);
+    }
+    let mut cycles = 0u64;
+    let mut last = SysTime::dwt_now();
+    let mut n = 0;
+    while n < changes {
+        if !wait_change() {
+            return Err(ClockError::ReferenceTimeout);
+        }
+        let now = SysTime::dwt_now();
+        cycles += now.wrapping_sub(last) as u64;
+        last = now;
+        n += 1;
+    }
+    Ok((cycles * rate as u64 / changes as u64) as u32)
+}
+
+fn hsi_trim() -> u8 {
+    unsafe { (((*RCC::ptr()).cr.read().bits() >> 3) & 0b11111) as u8 }
+}
+
+pub(super) fn set_hsi_trim(trim: u8) {
+    unsafe { (*RCC::ptr()).cr.modify(|r, w| w.bits((r.bits() & !(0b11111 << 3)) | (((trim & 0b11111) as u32) << 3))) }
+}
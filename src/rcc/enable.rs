@@ -0,0 +1,271 @@
+use super::Clock;
+use crate::pac::{self, RCC};
+
+/// Bus a peripheral is clocked from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Ahb,
+    Apb1,
+    Apb2,
+}
+impl Bus {
+    pub fn clock(self, clock: &Clock) -> u32 {
+        match self {
+            Bus::Ahb => clock.hclk,
+            Bus::Apb1 => clock.pclk1,
+            Bus::Apb2 => clock.pclk2,
+        }
+    }
+}
+
+pub trait BusClock {
+    const BUS: Bus;
+
+    /// Frequency of the bus the peripheral sits on.
+    fn bus_clock(clock: &Clock) -> u32 {
+        Self::BUS.clock(clock)
+    }
+}
+
+/// Peripheral clock gating through AHBENR/APB1ENR/APB2ENR.
+pub trait Enable: BusClock {
+    fn enable();
+    fn disable();
+    fn is_enabled() -> bool;
+}
+
+/// Peripheral reset through APB1RSTR/APB2RSTR (and AHBRSTR on connectivity line).
+pub trait Reset: BusClock {
+    fn reset();
+}
+
+/// Clock gating during Sleep mode. On F1 this only exists for the flash
+/// interface (FLITFEN, `pac::FLASH`) and SRAM (SRAMEN, `Sram`), every other
+/// enabled clock keeps running.
+pub trait LowPowerEnable {
+    fn low_power_enable();
+    fn low_power_disable();
+}
+
+/// Internal SRAM, which has no PAC peripheral of its own.
+pub struct Sram;
+
+macro_rules! low_power_enable {
+    ($($PER:ty => $bit:expr,)+) => {
+        $(
+            impl LowPowerEnable for $PER {
+                fn low_power_enable() {
+                    unsafe { (*RCC::ptr()).ahbenr.modify(|r, w| w.bits(r.bits() | (1 << $bit))) }
+                }
+
+                fn low_power_disable() {
+                    unsafe { (*RCC::ptr()).ahbenr.modify(|r, w| w.bits(r.bits() & !(1 << $bit))) }
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! bus_enable {
+    ($bus:ident, $enr:ident: $($PER:ident => $bit:expr,)+) => {
+        $(
+            impl BusClock for pac::$PER {
+                const BUS: Bus = Bus::$bus;
+            }
+
+            impl Enable for pac::$PER {
+                fn enable() {
+                    unsafe { (*RCC::ptr()).$enr.modify(|r, w| w.bits(r.bits() | (1 << $bit))) }
+                }
+
+                fn disable() {
+                    unsafe { (*RCC::ptr()).$enr.modify(|r, w| w.bits(r.bits() & !(1 << $bit))) }
+                }
+
+                fn is_enabled() -> bool {
+                    unsafe { (*RCC::ptr()).$enr.read().bits() & (1 << $bit) != 0 }
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! bus_reset {
+    ($rstr:ident: $($PER:ident => $bit:expr,)+) => {
+        $(
+            impl Reset for pac::$PER {
+                fn reset() {
+                    unsafe {
+                        (*RCC::ptr()).$rstr.modify(|r, w| w.bits(r.bits() | (1 << $bit)));
+                        (*RCC::ptr()).$rstr.modify(|r, w| w.bits(r.bits() & !(1 << $bit)));
+                    }
+                }
+            }
+        )+
+    };
+}
+
+low_power_enable!{
+    Sram => 2,
+    pac::FLASH => 4,
+}
+
+bus_enable!{
+    Ahb, ahbenr:
+    DMA1 => 0,
+    DMA2 => 1,
+    CRC => 6,
+}
+
+#[cfg(not(any(feature = "stm32f105", feature = "stm32f107")))]
+bus_enable!{
+    Ahb, ahbenr:
+    FSMC => 8,
+}
+
+#[cfg(feature = "stm32f103")]
+bus_enable!{
+    Ahb, ahbenr:
+    SDIO => 10,
+}
+
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+bus_enable!{
+    Ahb, ahbenr:
+    OTG_FS_GLOBAL => 12,
+}
+
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+bus_reset!{
+    ahbrstr:
+    OTG_FS_GLOBAL => 12,
+}
+
+// ETHMACEN only, the TX/RX clocks (bits 15, 16) are left to the driver
+#[cfg(feature = "stm32f107")]
+bus_enable!{
+    Ahb, ahbenr:
+    ETHERNET_MAC => 14,
+}
+
+#[cfg(feature = "stm32f107")]
+bus_reset!{
+    ahbrstr:
+    ETHERNET_MAC => 14,
+}
+
+macro_rules! apb {
+    ($bus:ident, $enr:ident, $rstr:ident: $($PER:ident => $bit:expr,)+) => {
+        bus_enable!{ $bus, $enr: $($PER => $bit,)+ }
+        bus_reset!{ $rstr: $($PER => $bit,)+ }
+    };
+}
+
+apb!{
+    Apb2, apb2enr, apb2rstr:
+    AFIO => 0,
+    GPIOA => 2,
+    GPIOB => 3,
+    GPIOC => 4,
+    GPIOD => 5,
+    GPIOE => 6,
+    ADC1 => 9,
+    SPI1 => 12,
+    USART1 => 14,
+}
+
+#[cfg(not(any(feature = "stm32f105", feature = "stm32f107")))]
+apb!{
+    Apb2, apb2enr, apb2rstr:
+    GPIOF => 7,
+    GPIOG => 8,
+}
+
+#[cfg(any(feature = "stm32f103", feature = "stm32f105", feature = "stm32f107"))]
+apb!{
+    Apb2, apb2enr, apb2rstr:
+    ADC2 => 10,
+}
+
+#[cfg(not(feature = "stm32f101"))]
+apb!{
+    Apb2, apb2enr, apb2rstr:
+    TIM1 => 11,
+}
+
+#[cfg(feature = "stm32f103")]
+apb!{
+    Apb2, apb2enr, apb2rstr:
+    TIM8 => 13,
+    ADC3 => 15,
+}
+
+#[cfg(feature = "stm32f100")]
+apb!{
+    Apb2, apb2enr, apb2rstr:
+    TIM15 => 16,
+    TIM16 => 17,
+    TIM17 => 18,
+}
+
+#[cfg(any(feature = "stm32f101", feature = "stm32f103"))]
+apb!{
+    Apb2, apb2enr, apb2rstr:
+    TIM9 => 19,
+    TIM10 => 20,
+    TIM11 => 21,
+}
+
+apb!{
+    Apb1, apb1enr, apb1rstr:
+    TIM2 => 0,
+    TIM3 => 1,
+    TIM4 => 2,
+    TIM5 => 3,
+    TIM6 => 4,
+    TIM7 => 5,
+    WWDG => 11,
+    SPI2 => 14,
+    SPI3 => 15,
+    USART2 => 17,
+    USART3 => 18,
+    UART4 => 19,
+    UART5 => 20,
+    I2C1 => 21,
+    I2C2 => 22,
+    BKP => 27,
+    PWR => 28,
+    DAC => 29,
+}
+
+#[cfg(any(feature = "stm32f100", feature = "stm32f101", feature = "stm32f103"))]
+apb!{
+    Apb1, apb1enr, apb1rstr:
+    TIM12 => 6,
+    TIM13 => 7,
+    TIM14 => 8,
+}
+
+#[cfg(feature = "stm32f103")]
+apb!{
+    Apb1, apb1enr, apb1rstr:
+    USB => 23,
+}
+
+#[cfg(any(feature = "stm32f103", feature = "stm32f105", feature = "stm32f107"))]
+apb!{
+    Apb1, apb1enr, apb1rstr:
+    CAN1 => 25,
+}
+
+#[cfg(any(feature = "stm32f105", feature = "stm32f107"))]
+apb!{
+    Apb1, apb1enr, apb1rstr:
+    CAN2 => 26,
+}
+
+#[cfg(feature = "stm32f100")]
+apb!{
+    Apb1, apb1enr, apb1rstr:
+    CEC => 30,
+}
@@ -1,68 +1,345 @@
+use core::cmp::Ordering;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
 use core::ptr::addr_of;
 use cortex_m_rt::exception;
 
 #[exception]
 fn SysTick() {
-    unsafe { G_TIME = G_TIME.wrapping_add(1) };
+    cortex_m::interrupt::free(|_| advance(unsafe { G_TICK_US }));
 }
 
+/// Adds `us` to the time. Both halves change together, so a higher priority
+/// reader never sees a torn count. Call with interrupts disabled.
+fn advance(us: u32) {
+    unsafe {
+        let (lo, carry) = G_TIME.overflowing_add(us);
+        G_TIME = lo;
+        if carry {
+            G_TIME_HI = G_TIME_HI.wrapping_add(1);
+        }
+    }
+}
+
+/// Microseconds counted up to the last tick. Lock free, a torn read is
+/// detected by the high half changing.
+fn tick_time() -> u64 {
+    loop {
+        let hi = unsafe { core::ptr::read_volatile(addr_of!(G_TIME_HI)) };
+        let lo = unsafe { core::ptr::read_volatile(addr_of!(G_TIME)) };
+        if hi == unsafe { core::ptr::read_volatile(addr_of!(G_TIME_HI)) } {
+            return ((hi as u64) << 32) | lo as u64;
+        }
+    }
+}
+
+/// SysTick clocks since the CYCCNT reading `since`.
+fn stopped_clocks(since: u32) -> u32 {
+    let cycles = SysTime::dwt_now().wrapping_sub(since);
+    if unsafe { G_TICK_DIV8 } {
+        cycles / 8
+    }
+    else {
+        cycles
+    }
+}
+
+// Time in microseconds at the last tick, split in halves for the M3
 static mut G_TIME: u32 = 0;
-static mut G_AHB_CLK: u32 = 8;
+static mut G_TIME_HI: u32 = 0;
+static mut G_AHB_CLK: u32 = 8_000_000;
+static mut G_TICK_US: u32 = 1000;
+static mut G_TICK_DIV8: bool = false;
+
+const STK_LOAD_MAX: u32 = 0xFF_FFFF;
+const ICSR_PENDSTSET: u32 = 1 << 26;
+// Shortest partial tick after idle, in SysTick clocks
+const IDLE_LEFT_MIN: u32 = 16;
+const ICSR_PENDSTCLR: u32 = 1 << 25;
+
+/// SysTick settings, see `SysTime::config`.
+#[derive(Debug, Clone, Copy)]
+pub struct TickConfig {
+    rate: u32,
+    hclk_div8: bool,
+}
+impl TickConfig {
+    /// Tick rate, 100 Hz..10 kHz and a divisor of 1 MHz. Default 1 kHz.
+    pub const fn rate(mut self, hz: u32) -> Self {
+        self.rate = hz;
+        self
+    }
+
+    /// Clocks SysTick from HCLK/8 instead of HCLK, for low power operation.
+    pub const fn hclk_div8(mut self) -> Self {
+        self.hclk_div8 = true;
+        self
+    }
+
+    /// Starts SysTick, pass `Clock::hclk` here. Panics if the tick rate is
+    /// unsupported or does not fit the 24 bit SysTick at this clock.
+    pub fn start(self, hclk: u32) {
+        assert!((100..=10_000).contains(&self.rate) && 1_000_000 % self.rate == 0,
+            "SysTick rate must be 100 Hz..10 kHz and divide 1 MHz");
+        let load = tick_load(hclk, 1_000_000 / self.rate, self.hclk_div8)
+            .expect("SysTick rate out of range for this clock");
 
-pub struct SysTime{}
-impl SysTime {
-    pub fn new(ahb_clk: u32){
         let stk = unsafe { &*crate::pac::STK::ptr() };
-        unsafe { G_AHB_CLK = ahb_clk / 1_000_000 };
-        stk.load_.write(|w| unsafe { w.bits(ahb_clk / 1000 - 1) });
+        unsafe {
+            G_AHB_CLK = hclk;
+            G_TICK_US = 1_000_000 / self.rate;
+            G_TICK_DIV8 = self.hclk_div8;
+        }
+        stk.load_.write(|w| unsafe { w.bits(load) });
         stk.val.write(|w|unsafe{ w.bits(0) });
         stk.ctrl.modify(|_, w|w
             .tickint().set_bit()
-            .clksource().set_bit()
+            .clksource().bit(!self.hclk_div8)
             .enable().set_bit());
-        SysTime::dwt_enable(); 
+        SysTime::dwt_enable();
     }
-    
+}
+
+/// SysTick reload value for one tick of `tick_us` at `hclk`, `None` if the
+/// tick does not fit the 24 bit counter.
+fn tick_load(hclk: u32, tick_us: u32, div8: bool) -> Option<u32> {
+    let src = if div8 { hclk / 8 } else { hclk };
+    let cycles = src as u64 * tick_us as u64 / 1_000_000;
+    if cycles < 2 || cycles > STK_LOAD_MAX as u64 + 1 {
+        None
+    }
+    else {
+        Some(cycles as u32 - 1)
+    }
+}
+
+pub struct SysTime{}
+impl SysTime {
+    /// Starts the 1 ms SysTick from HCLK, pass `Clock::hclk` here
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(hclk: u32){
+        SysTime::config().start(hclk);
+    }
+
+    /// SysTick settings builder, 1 kHz from HCLK by default.
+    pub const fn config() -> TickConfig {
+        TickConfig { rate: 1000, hclk_div8: false }
+    }
+
+    /// Stops SysTick and clears a pending tick, disables the DWT cycle
+    /// counter and trace, and forgets HCLK (back to HSI). The tick count is kept.
+    pub fn deinit() {
+        let stk = unsafe { &*crate::pac::STK::ptr() };
+        stk.ctrl.reset();
+        stk.load_.reset();
+        stk.val.reset();
+        unsafe {
+            (*crate::pac::SCB::PTR).icsr.write(ICSR_PENDSTCLR);
+            (*crate::pac::DWT::PTR).ctrl.modify(|r| r & !(1 << 0));
+            (*crate::pac::DCB::PTR).demcr.modify(|r| r & !(1 << 24));
+            G_AHB_CLK = 8_000_000;
+            G_TICK_US = 1000;
+            G_TICK_DIV8 = false;
+        }
+    }
+
+    /// Whether the running SysTick can keep its rate at `hclk`, checked by
+    /// `RccConfig` before it changes the clocks.
+    pub(crate) fn tick_fits(hclk: u32) -> bool {
+        let stk = unsafe { &*crate::pac::STK::ptr() };
+        let (tick_us, div8) = unsafe { (G_TICK_US, G_TICK_DIV8) };
+        stk.ctrl.read().enable().bit_is_clear() || tick_load(hclk, tick_us, div8).is_some()
+    }
+
+    /// Records a new HCLK and reprograms SysTick if it is running, keeping the
+    /// tick count. Panics if the tick rate is impossible at `hclk`, see `tick_fits`.
+    pub(crate) fn reload(hclk: u32) {
+        let stk = unsafe { &*crate::pac::STK::ptr() };
+        let scb = unsafe { &*crate::pac::SCB::PTR };
+        cortex_m::interrupt::free(|_| {
+            unsafe { G_AHB_CLK = hclk };
+            if stk.ctrl.read().enable().bit_is_clear() {
+                return;
+            }
+            // Fold the running tick into the count so time does not jump back
+            let now = SysTime::now_us64();
+            unsafe { scb.icsr.write(ICSR_PENDSTCLR) };
+            advance((now - tick_time()) as u32);
+            let load = unsafe { tick_load(hclk, G_TICK_US, G_TICK_DIV8) }
+                .expect("SysTick rate out of range for this clock");
+            stk.load_.write(|w| unsafe { w.bits(load) });
+            stk.val.write(|w|unsafe{ w.bits(0) });
+        });
+    }
+
+    /// Milliseconds since `SysTime::new`, wraps after ~49 days.
     pub fn now() -> u32 {
-        unsafe { core::ptr::read_volatile(addr_of!(G_TIME)) }   
+        SysTime::now64() as u32
+    }
+
+    /// Milliseconds since `SysTime::new`, never wraps in practice. Usable
+    /// from thread and interrupt context.
+    pub fn now64() -> u64 {
+        SysTime::now_us64() / 1000
+    }
+
+    /// Microseconds since `SysTime::new`, never wraps in practice. Usable from
+    /// thread and interrupt context, including inside `interrupt::free`.
+    pub fn now_us64() -> u64 {
+        let stk = unsafe { &*crate::pac::STK::ptr() };
+        let scb = unsafe { &*crate::pac::SCB::PTR };
+        cortex_m::interrupt::free(|_| {
+            let tick_us = unsafe { G_TICK_US };
+            let mut us = tick_time();
+            let load = stk.load_.read().bits();
+            let mut val = stk.val.read().bits();
+            // A wrap whose SysTick has not run yet is not in the count
+            if scb.icsr.read() & ICSR_PENDSTSET != 0 {
+                val = stk.val.read().bits();
+                us += tick_us as u64;
+            }
+            // 0 is the end of a tick, counted above, or a fresh start after a
+            // VAL write, before the counter has taken LOAD
+            if val == 0 {
+                return us;
+            }
+            us + (load.saturating_sub(val) as u64 * tick_us as u64) / (load as u64 + 1)
+        })
+    }
+
+    /// Tickless idle: sleeps in WFI for up to `max`, with SysTick reprogrammed
+    /// to fire only then instead of every tick, and accounts the slept time on
+    /// wakeup. Any other interrupt ends the sleep early. The sleep is cut to
+    /// whole ticks and to the 24 bit SysTick range (~0.23 s at 72 MHz HCLK,
+    /// ~1.8 s from HCLK/8), so call it in a loop.
+    pub fn idle(max: DurationUs) {
+        let stk = unsafe { &*crate::pac::STK::ptr() };
+        let scb = unsafe { &*crate::pac::SCB::PTR };
+        SysTime::dwt_enable();
+        cortex_m::interrupt::free(|_| {
+            if stk.ctrl.read().enable().bit_is_clear() {
+                return;
+            }
+            let tick_us = unsafe { G_TICK_US };
+            let load = stk.load_.read().bits();
+            let tick_cycles = load + 1;
+            let max_ticks = max.ticks() / tick_us;
+            // Nothing to skip, no room for two ticks in 24 bits, or a tick is
+            // already due
+            if max_ticks < 2 || STK_LOAD_MAX - load < tick_cycles || scb.icsr.read() & ICSR_PENDSTSET != 0 {
+                return;
+            }
+
+            // SysTick is stopped while it is reprogrammed. CYCCNT measures each
+            // stop and it is taken off the next count, so no time is lost.
+            stk.ctrl.modify(|_, w| w.enable().clear_bit());
+            let stopped = SysTime::dwt_now();
+            if scb.icsr.read() & ICSR_PENDSTSET != 0 {
+                unsafe { scb.icsr.write(ICSR_PENDSTCLR) };
+                advance(tick_us);
+            }
+            // 0 still has the reload into the next tick ahead
+            let val = match stk.val.read().bits() {
+                0 => tick_cycles,
+                val => val,
+            };
+            let ticks = max_ticks.min((STK_LOAD_MAX + 1 - val) / tick_cycles + 1);
+
+            // The rest of the current tick plus the whole ticks to skip
+            let lost = stopped_clocks(stopped);
+            let period = val + (ticks - 1) * tick_cycles - lost;
+            stk.load_.write(|w| unsafe { w.bits(period - 1) });
+            stk.val.write(|w| unsafe { w.bits(0) });
+            stk.ctrl.modify(|_, w| w.enable().set_bit());
+            cortex_m::asm::wfi();
+            stk.ctrl.modify(|_, w| w.enable().clear_bit());
+            let stopped = SysTime::dwt_now();
+
+            // Still 0 if woken before the counter took the new reload value
+            let counted = match stk.val.read().bits() {
+                0 => 0,
+                now => period - now,
+            };
+            let elapsed = lost + if scb.icsr.read() & ICSR_PENDSTSET != 0 {
+                unsafe { scb.icsr.write(ICSR_PENDSTCLR) };
+                period + counted
+            }
+            else {
+                counted
+            };
+
+            // Ticks passed and the part of the current tick still to run
+            let (mut passed, mut left) = if elapsed < val {
+                (0, val - elapsed)
+            }
+            else {
+                (1 + (elapsed - val) / tick_cycles, tick_cycles - (elapsed - val) % tick_cycles)
+            };
+            // Too short to see the counter take it, count the tick now and run
+            // the next one a little longer instead
+            let lost = stopped_clocks(stopped);
+            if left < lost + IDLE_LEFT_MIN {
+                passed += 1;
+                left += tick_cycles;
+            }
+            advance(passed * tick_us);
+
+            // LOAD is taken on the next SysTick clock edge, up to 8 HCLK
+            // cycles away with HCLK/8, so restore it only once VAL shows it
+            stk.load_.write(|w| unsafe { w.bits(left - lost - 1) });
+            stk.val.write(|w| unsafe { w.bits(0) });
+            stk.ctrl.modify(|_, w| w.enable().set_bit());
+            while stk.val.read().bits() == 0 {}
+            stk.load_.write(|w| unsafe { w.bits(load) });
+        });
+    }
+
+    /// Tickless idle until `deadline`, see `idle`.
+    pub fn idle_until(deadline: InstantUs) {
+        SysTime::idle(deadline.duration_since(InstantUs::now()));
     }
 
     pub fn delay<T: QuantTime>(delay: T) {
         delay.delay();
     }
 
-    /// Returns microseconds since power on systime
-    ///
-    /// # Safety
-    ///
-    /// - Do not call this function inside an `interrupt::free` critical section
-    pub unsafe fn now_us() -> u32 {
-        let stk = unsafe{ &*crate::pac::STK::ptr() };
-        let  cur_val: u32;
-        let  ms: u32;
-
-        cortex_m::interrupt::disable();
-            cur_val = stk.val.read().bits();
-            if  stk.ctrl.read().countflag().bit() {
-                unsafe { G_TIME += 1 };
-            }
-            ms = SysTime::now() as u32;
-        unsafe { cortex_m::interrupt::enable(); }
-        unsafe { ms * 1000 + 999 - cur_val / G_AHB_CLK }
+    /// Busy-waits `cycles` core clock cycles on the DWT cycle counter, exact
+    /// regardless of flash wait states and interrupts (up to the overshoot of
+    /// a pending ISR). Needs DWT enabled, as done by `SysTime::new`.
+    #[inline(always)]
+    pub fn delay_cycles(cycles: u32) {
+        let start = SysTime::dwt_now();
+        while SysTime::dwt_now().wrapping_sub(start) < cycles {}
+    }
+
+    /// Delay provider for `embedded-hal` drivers, needs SysTime started.
+    pub fn hal_delay() -> Delay {
+        SysTime::dwt_enable();
+        Delay {}
+    }
+
+    /// Microseconds since `SysTime::new`, wraps after ~71 minutes. Usable
+    /// from thread and interrupt context, including inside `interrupt::free`.
+    pub fn now_us() -> u32 {
+        SysTime::now_us64() as u32
+    }
+
+    /// AHB clock in Hz as last passed to `SysTime::new` (HSI after reset)
+    pub(crate) fn ahb_hz() -> u32 {
+        unsafe { core::ptr::read_volatile(addr_of!(G_AHB_CLK)) }
     }
-    
 
     #[inline(always)]
     pub fn dwt_now() -> u32 {
         unsafe { (*crate::pac::DWT::PTR).cyccnt.read() }
     }
     
+    #[deprecated(note = "resets CYCCNT under every other user, use `profile::CycleMeasure`")]
     #[inline(always)]
     pub fn dwt_start() {
         unsafe { (*crate::pac::DWT::PTR).cyccnt.write(0)};
     }
     
-    fn dwt_enable() {
+    pub(crate) fn dwt_enable() {
         unsafe {
             (*crate::pac::DCB::PTR).demcr.modify(|r| r | (1 << 24));
             (*crate::pac::DWT::PTR).ctrl.modify(|r| r | (1 << 0));
@@ -78,6 +355,7 @@ pub trait U32time {
     fn mhz(self) -> u32;
     fn ms(self) -> MilliSeconds;
     fn us(self) -> MicroSeconds;
+    fn ns(self) -> NanoSeconds;
 }
 
 impl U32time for u32 {
@@ -100,6 +378,10 @@ impl U32time for u32 {
     fn us(self) -> MicroSeconds {
         MicroSeconds(self)
     }
+
+    fn ns(self) -> NanoSeconds {
+        NanoSeconds(self)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -108,31 +390,296 @@ pub struct MilliSeconds(pub u32);
 #[derive(Debug, Clone, Copy)]
 pub struct MicroSeconds(pub u32);
 
+#[derive(Debug, Clone, Copy)]
+pub struct NanoSeconds(pub u32);
+
+/// Point in time counted in ticks of `HZ`. The counter wraps, so instants
+/// only compare correctly when less than half the wrap period apart
+/// (~24 days for ms, ~35 minutes for µs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instant<const HZ: u32>(u32);
+
+/// Span of time counted in ticks of `HZ`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Duration<const HZ: u32>(u32);
+
+pub type InstantMs = Instant<1_000>;
+pub type InstantUs = Instant<1_000_000>;
+pub type DurationMs = Duration<1_000>;
+pub type DurationUs = Duration<1_000_000>;
+
+impl<const HZ: u32> Instant<HZ> {
+    pub const fn from_ticks(ticks: u32) -> Self {
+        Instant(ticks)
+    }
+
+    pub const fn ticks(self) -> u32 {
+        self.0
+    }
+
+    /// Time since `earlier`, `None` if `earlier` is actually later.
+    pub const fn checked_duration_since(self, earlier: Self) -> Option<Duration<HZ>> {
+        let diff = self.0.wrapping_sub(earlier.0);
+        if (diff as i32) < 0 {
+            None
+        }
+        else {
+            Some(Duration(diff))
+        }
+    }
+
+    /// Time since `earlier`, zero if `earlier` is actually later.
+    pub const fn duration_since(self, earlier: Self) -> Duration<HZ> {
+        match self.checked_duration_since(earlier) {
+            Some(duration) => duration,
+            None => Duration(0),
+        }
+    }
+}
+
+impl InstantMs {
+    /// Current SysTick time.
+    pub fn now() -> Self {
+        Instant(SysTime::now())
+    }
+
+    pub fn elapsed(self) -> DurationMs {
+        Self::now().duration_since(self)
+    }
+}
+
+impl InstantUs {
+    /// Current SysTick time with microsecond resolution.
+    pub fn now() -> Self {
+        Instant(SysTime::now_us())
+    }
+
+    pub fn elapsed(self) -> DurationUs {
+        Self::now().duration_since(self)
+    }
+}
+
+impl<const HZ: u32> PartialOrd for Instant<HZ> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some((self.0.wrapping_sub(other.0) as i32).cmp(&0))
+    }
+}
+
+impl<const HZ: u32> Add<Duration<HZ>> for Instant<HZ> {
+    type Output = Self;
+    fn add(self, rhs: Duration<HZ>) -> Self {
+        Instant(self.0.wrapping_add(rhs.0))
+    }
+}
+
+impl<const HZ: u32> AddAssign<Duration<HZ>> for Instant<HZ> {
+    fn add_assign(&mut self, rhs: Duration<HZ>) {
+        *self = *self + rhs;
+    }
+}
+
+impl<const HZ: u32> Sub<Duration<HZ>> for Instant<HZ> {
+    type Output = Self;
+    fn sub(self, rhs: Duration<HZ>) -> Self {
+        Instant(self.0.wrapping_sub(rhs.0))
+    }
+}
+
+impl<const HZ: u32> SubAssign<Duration<HZ>> for Instant<HZ> {
+    fn sub_assign(&mut self, rhs: Duration<HZ>) {
+        *self = *self - rhs;
+    }
+}
+
+/// Wrapping difference, use `checked_duration_since` if the order is unknown.
+impl<const HZ: u32> Sub for Instant<HZ> {
+    type Output = Duration<HZ>;
+    fn sub(self, rhs: Self) -> Duration<HZ> {
+        Duration(self.0.wrapping_sub(rhs.0))
+    }
+}
+
+impl<const HZ: u32> Duration<HZ> {
+    pub const fn from_ticks(ticks: u32) -> Self {
+        Duration(ticks)
+    }
+
+    pub const fn ticks(self) -> u32 {
+        self.0
+    }
+
+    pub const fn checked_add(self, rhs: Self) -> Option<Self> {
+        match self.0.checked_add(rhs.0) {
+            Some(ticks) => Some(Duration(ticks)),
+            None => None,
+        }
+    }
+
+    pub const fn checked_sub(self, rhs: Self) -> Option<Self> {
+        match self.0.checked_sub(rhs.0) {
+            Some(ticks) => Some(Duration(ticks)),
+            None => None,
+        }
+    }
+}
+
+/// Saturates at the longest duration.
+impl<const HZ: u32> Add for Duration<HZ> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Duration(self.0.saturating_add(rhs.0))
+    }
+}
+
+/// Saturates at zero.
+impl<const HZ: u32> Sub for Duration<HZ> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Duration(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl From<MilliSeconds> for DurationMs {
+    fn from(ms: MilliSeconds) -> Self {
+        Duration(ms.0)
+    }
+}
+
+impl From<MicroSeconds> for DurationUs {
+    fn from(us: MicroSeconds) -> Self {
+        Duration(us.0)
+    }
+}
+
 
 pub trait QuantTime{
     fn delay(self);
 }
 
+/// Spins `ticks` periods of a `per_sec` clock in DWT cycles, rounded up and
+/// split so that long waits do not overflow the 32 bit counter. Enables the
+/// DWT itself, so it also works without `SysTime::new` or after `deinit`.
+fn delay_ticks(ticks: u32, per_sec: u32) {
+    SysTime::dwt_enable();
+    let mut cycles = (ticks as u64 * SysTime::ahb_hz() as u64).div_ceil(per_sec as u64);
+    while cycles > 0 {
+        let step = cycles.min(u32::MAX as u64 / 2);
+        SysTime::delay_cycles(step as u32);
+        cycles -= step;
+    }
+}
+
 impl QuantTime for MicroSeconds {
     fn delay(self) {
-        let us = unsafe {
-            if G_AHB_CLK <= 24 {
-                G_AHB_CLK * self.0
-            }
-            else if G_AHB_CLK <= 48 {
-                (G_AHB_CLK / 2) * self.0
-            }
-            else {
-                (G_AHB_CLK / 3) * self.0
-            }
-        };
-        cortex_m::asm::delay(us);
+        delay_ticks(self.0, 1_000_000);
+    }
+}
+
+impl QuantTime for NanoSeconds {
+    fn delay(self) {
+        delay_ticks(self.0, 1_000_000_000);
     }
 }
 
 impl QuantTime for MilliSeconds {
     fn delay(self) {
-        let delay = SysTime::now();
-        while SysTime::now() - delay < self.0 {}
+        DurationMs::from(self).delay();
+    }
+}
+
+impl QuantTime for DurationMs {
+    fn delay(self) {
+        let start = InstantMs::now();
+        while start.elapsed() < self {}
+    }
+}
+
+impl QuantTime for DurationUs {
+    fn delay(self) {
+        MicroSeconds(self.0).delay();
+    }
+}
+
+
+/// `embedded-hal` delay on the SysTime clock: DWT cycles below a millisecond,
+/// the microsecond SysTick time above. Without a running SysTick all delays
+/// count DWT cycles.
+#[derive(Debug, Clone, Copy)]
+pub struct Delay{}
+impl Delay {
+    fn wait(ticks: u32, per_sec: u32) {
+        let stk = unsafe { &*crate::pac::STK::ptr() };
+        if stk.ctrl.read().enable().bit_is_clear() {
+            delay_ticks(ticks, per_sec);
+            return;
+        }
+        let end = SysTime::now_us64() + ticks as u64 * 1_000_000 / per_sec as u64;
+        while SysTime::now_us64() < end {}
+    }
+}
+
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        NanoSeconds(ns).delay();
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        if us < 1000 {
+            self.delay_ns(us * 1000);
+        }
+        else {
+            Delay::wait(us, 1_000_000);
+        }
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        Delay::wait(ms, 1000);
+    }
+}
+
+#[cfg(feature = "embedded-hal-02")]
+macro_rules! hal02_delay {
+    ($($t:ty),*) => {$(
+        impl embedded_hal_02::blocking::delay::DelayUs<$t> for Delay {
+            fn delay_us(&mut self, us: $t) {
+                embedded_hal::delay::DelayNs::delay_us(self, us as u32);
+            }
+        }
+
+        impl embedded_hal_02::blocking::delay::DelayMs<$t> for Delay {
+            fn delay_ms(&mut self, ms: $t) {
+                embedded_hal::delay::DelayNs::delay_ms(self, ms as u32);
+            }
+        }
+    )*};
+}
+
+#[cfg(feature = "embedded-hal-02")]
+hal02_delay!(u8, u16, u32);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instant_across_wrap() {
+        let before = InstantMs::from_ticks(u32::MAX - 1);
+        let after = InstantMs::from_ticks(2);
+        assert_eq!(after.checked_duration_since(before), Some(DurationMs::from_ticks(4)));
+        assert_eq!(before.checked_duration_since(after), None);
+        assert_eq!(before.duration_since(after), DurationMs::from_ticks(0));
+        assert!(before < after);
+        assert!(after > before);
+        assert_eq!(before + DurationMs::from_ticks(4), after);
+    }
+
+    #[test]
+    fn duration_saturates() {
+        let max = DurationUs::from_ticks(u32::MAX);
+        let one = DurationUs::from_ticks(1);
+        assert_eq!(max + one, max);
+        assert_eq!(one - max, DurationUs::from_ticks(0));
+        assert_eq!(max.checked_add(one), None);
+        assert_eq!(one.checked_sub(max), None);
     }
 }